@@ -0,0 +1,112 @@
+use sfml::graphics::View;
+
+use diag::bounding_box;
+use frame::Frame;
+use math::{lerp, vec2};
+use Object;
+
+/* A box containing every body as drawn in frame */
+pub fn framed_box(state: &[Object], frame: &Frame) -> (vec2<f32>, vec2<f32>) {
+	let (lo, hi) = bounding_box(state);
+	if frame.angle == 0.0 {
+		(frame.apply(lo), frame.apply(hi))
+	} else {
+		/* Rotated boxes need room for every orientation */
+		let c = frame.apply((lo + hi) * 0.5);
+		let r = (hi - lo).norm::<f32>() * 0.5;
+		(c - vec2::new(r, r), c + vec2::new(r, r))
+	}
+}
+
+/*
+ * The center and size of a view framing the box from lo to hi with a small margin, keeping the
+ * aspect ratio (width / height) of the window. A degenerate box (a single body, or all bodies in
+ * one spot) just gets centered at the current size.
+ */
+pub fn fit(lo: vec2<f32>, hi: vec2<f32>, aspect: f32, size: vec2<f32>) -> (vec2<f32>, vec2<f32>) {
+	const MARGIN: f32 = 1.1;
+	let center = (lo + hi) * 0.5;
+	let ext = (hi - lo) * MARGIN;
+
+	if ext.x > 0.0 || ext.y > 0.0 {
+		let w = ext.x.max(ext.y * aspect);
+		(center, vec2::new(w, w / aspect))
+	} else {
+		(center, size)
+	}
+}
+
+/* Move the view a fraction k of the way towards the given center and size */
+pub fn approach(view: &mut View, center: vec2<f32>, size: vec2<f32>, k: f32) {
+	let (c, s) = (view.center(), view.size());
+	let c = lerp(vec2::new(c.x, c.y), center, k);
+	let s = lerp(vec2::new(s.x, s.y), size, k);
+	view.set_center((c.x, c.y));
+	view.set_size((s.x, s.y));
+}
+
+/* The largest "round" length (1, 2, or 5 times a power of ten) no longer than span */
+pub fn nice_length(span: f32) -> f32 {
+	if !(span > 0.0) {
+		return 0.0;
+	}
+	let pow = 10f32.powf(span.log10().floor());
+	let m = span / pow;
+	pow * if m >= 5.0 { 5.0 } else if m >= 2.0 { 2.0 } else { 1.0 }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn at(x: f32, y: f32) -> Object {
+		Object {
+			s: vec2::new(x, y),
+			v: vec2::new(0.0, 0.0),
+			m: 1.0,
+			r: 0.0,
+			fixed: false,
+		}
+	}
+
+	#[test]
+	fn nice_lengths() {
+		for &(span, nice) in [(1.0, 1.0), (3.7, 2.0), (7.0, 5.0), (25.0, 20.0), (0.0042, 0.002),
+			(1.5e11, 1.0e11)].iter() {
+			let got = nice_length(span);
+			assert!((got - nice).abs() <= 1.0e-5 * nice, "{} -> {}, not {}", span, got, nice);
+		}
+		assert_eq!(nice_length(0.0), 0.0);
+		assert_eq!(nice_length(-3.0), 0.0);
+		assert_eq!(nice_length(::std::f32::NAN), 0.0);
+	}
+
+	#[test]
+	fn fit_keeps_aspect() {
+		let (center, size) = fit(vec2::new(0.0, 0.0), vec2::new(2.0, 1.0), 4.0,
+			vec2::new(1.0, 1.0));
+		assert_eq!((center.x, center.y), (1.0, 0.5));
+		assert!((size.x - 4.4).abs() < 1.0e-5 && (size.y - 1.1).abs() < 1.0e-5);
+
+		let one = vec2::new(3.0, 3.0);
+		let (center, size) = fit(one, one, 4.0, vec2::new(8.0, 2.0));
+		assert_eq!((center.x, center.y, size.x, size.y), (3.0, 3.0, 8.0, 2.0));
+	}
+
+	#[test]
+	fn framed() {
+		let state = [at(1.0, 1.0), at(3.0, 2.0), at(2.0, 1.5)];
+		let frame = Frame {
+			origin: vec2::new(1.0, 1.0),
+			angle: 0.0,
+		};
+		let (lo, hi) = framed_box(&state, &frame);
+		assert_eq!((lo.x, lo.y, hi.x, hi.y), (0.0, 0.0, 2.0, 1.0));
+
+		/* However the box is turned, it fits in a square around its center */
+		let turned = Frame { angle: 1.0, ..frame };
+		let (lo, hi) = framed_box(&state, &turned);
+		assert!((hi.x - lo.x - 5.0f32.sqrt()).abs() < 1.0e-5);
+		assert!((hi.y - lo.y - 5.0f32.sqrt()).abs() < 1.0e-5);
+	}
+}
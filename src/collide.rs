@@ -0,0 +1,185 @@
+use hash::candidate_pairs;
+use Object;
+
+/* What happens when two bodies touch */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Collisions {
+	/* Bounce off each other */
+	Elastic,
+	/* Stick together into one body */
+	Merge,
+}
+
+/* How much a body resists a change in velocity; fixed bodies can't be budged at all */
+fn inv_mass(o: &Object) -> f32 {
+	if o.fixed { 0.0 } else { 1.0 / o.m }
+}
+
+/*
+ * Bounce every pair of overlapping bodies off each other, conserving momentum and keeping
+ * restitution^2 of the kinetic energy of their approach (so 1 is perfectly elastic). Overlapping
+ * bodies are pushed apart until they just touch, and pairs which are already moving apart aren't
+ * bounced again so that touching bodies don't get kicked back and forth every step.
+ */
+pub fn collide(state: &mut [Object], restitution: f32) {
+	/* Point masses never touch */
+	if state.iter().all(|o| o.r == 0.0) {
+		return;
+	}
+
+	for (i, j) in candidate_pairs(state) {
+		let (a, b) = (state[i], state[j]);
+		if a.m == 0.0 || b.m == 0.0 {
+			continue;
+		}
+		let ba = b.s - a.s;
+		let dsq = ba.normsq();
+		if dsq == 0.0 || dsq >= (a.r + b.r) * (a.r + b.r) {
+			continue;
+		}
+
+		let n = ba.normalize();
+		let (wa, wb) = (inv_mass(&a), inv_mass(&b));
+		if wa + wb == 0.0 {
+			continue;
+		}
+
+		/* The lighter body moves further */
+		let overlap = a.r + b.r - dsq.sqrt();
+		state[i].s = a.s - n * (overlap * wa / (wa + wb));
+		state[j].s = b.s + n * (overlap * wb / (wa + wb));
+
+		let vn = (b.v - a.v).dot(n);
+		if vn >= 0.0 {
+			continue;
+		}
+		let imp = (1.0 + restitution) * vn / (wa + wb);
+		state[i].v = a.v + n * (imp * wa);
+		state[j].v = b.v - n * (imp * wb);
+	}
+}
+
+/* Tracers pass straight through everything */
+fn touching(a: &Object, b: &Object) -> bool {
+	a.m != 0.0 && b.m != 0.0 && (b.s - a.s).normsq() < (a.r + b.r) * (a.r + b.r)
+}
+
+/* A single body with the mass, momentum, and area of both; fixed bodies stay put */
+fn combine(a: &Object, b: &Object) -> Object {
+	let m = a.m + b.m;
+	let (s, v) = if a.fixed {
+		(a.s, a.v)
+	} else if b.fixed {
+		(b.s, b.v)
+	} else {
+		((a.s * a.m + b.s * b.m) * (1.0 / m), (a.v * a.m + b.v * b.m) * (1.0 / m))
+	};
+	Object {
+		s,
+		v,
+		m,
+		r: (a.r * a.r + b.r * b.r).sqrt(),
+		fixed: a.fixed || b.fixed,
+	}
+}
+
+/*
+ * Merge every group of touching bodies into one, returning the (ascending) indices of the bodies
+ * which were absorbed. The heavier body of each pair survives in its own slot.
+ */
+pub fn merge(state: &mut Vec<Object>) -> Vec<usize> {
+	let mut gone = vec![false; state.len()];
+	/*
+	 * A merged body may have grown into another, so keep going (with a fresh hash) until nothing
+	 * touches
+	 */
+	let mut merged = true;
+	while merged {
+		merged = false;
+		for (i, j) in candidate_pairs(state.as_slice()) {
+			if gone[i] || gone[j] || !touching(&state[i], &state[j]) {
+				continue;
+			}
+
+			let (keep, lose) = if state[j].m > state[i].m { (j, i) } else { (i, j) };
+			state[keep] = combine(&state[keep], &state[lose]);
+			gone[lose] = true;
+			merged = true;
+		}
+	}
+
+	let removed = (0..state.len()).filter(|&i| gone[i]).collect();
+	let mut i = 0;
+	state.retain(|_| {
+		i += 1;
+		!gone[i - 1]
+	});
+	removed
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use diag::total_momentum;
+	use math::vec2;
+
+	fn ball(x: f32, vx: f32, m: f32, r: f32) -> Object {
+		Object {
+			s: vec2::new(x, 0.0),
+			v: vec2::new(vx, 0.0),
+			m,
+			r,
+			fixed: false,
+		}
+	}
+
+	#[test]
+	fn equal_masses_swap() {
+		let mut state = [ball(0.0, 1.0, 1.0, 1.0), ball(1.5, -2.0, 1.0, 1.0)];
+		collide(&mut state, 1.0);
+		assert_eq!((state[0].v.x, state[1].v.x), (-2.0, 1.0));
+		/* Pushed apart until they just touch */
+		assert_eq!((state[0].s.x, state[1].s.x), (-0.25, 1.75));
+
+		/* Now they're separating, so another pass leaves them be */
+		collide(&mut state, 1.0);
+		assert_eq!((state[0].v.x, state[1].v.x), (-2.0, 1.0));
+	}
+
+	#[test]
+	fn inelastic_and_fixed() {
+		let mut state = [ball(0.0, 1.0, 1.0, 1.0), ball(1.5, -2.0, 1.0, 1.0)];
+		collide(&mut state, 0.0);
+		assert_eq!((state[0].v.x, state[1].v.x), (-0.5, -0.5));
+
+		let mut state = [ball(0.0, 0.0, 1.0e6, 1.0), ball(1.5, -2.0, 1.0, 1.0)];
+		state[0].fixed = true;
+		collide(&mut state, 1.0);
+		assert_eq!((state[0].v.x, state[0].s.x), (0.0, 0.0));
+		assert_eq!((state[1].v.x, state[1].s.x), (2.0, 2.0));
+	}
+
+	#[test]
+	fn merge_conserves_mass_and_momentum() {
+		let mut state = vec![
+			ball(0.0, 1.0, 1.0, 1.0),
+			ball(10.0, 0.0, 5.0, 1.0),
+			ball(1.5, -3.0, 2.0, 1.0),
+			/* Touches the third body, but not the first */
+			ball(2.7, 0.5, 0.5, 0.5),
+			/* Tracers pass straight through */
+			ball(0.5, 7.0, 0.0, 1.0),
+		];
+		let (m, p) = (state.iter().map(|o| o.m).sum::<f32>(), total_momentum(state.as_slice()));
+
+		assert_eq!(merge(&mut state), vec![0, 3]);
+		assert_eq!(state.len(), 3);
+		assert_eq!(state.iter().map(|o| o.m).sum::<f32>(), m);
+		assert!((total_momentum(state.as_slice()) - p).norm::<f32>() < 1.0e-5);
+		/* The heaviest of the group survives, with all of their area */
+		assert_eq!(state[1].m, 3.5);
+		assert!((state[1].r - 2.25f32.sqrt()).abs() < 1.0e-6);
+		assert_eq!(state[2].m, 0.0);
+	}
+}
@@ -0,0 +1,545 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+
+use rayon::prelude::*;
+
+use math::{vec2, Additive};
+use {min_image, Object, SimConfig};
+
+/* Total kinetic energy of the system */
+pub fn kinetic_energy(state: &[Object]) -> f32 {
+	state.par_iter()
+		.map(|o| 0.5 * o.m * o.v.normsq())
+		.sum()
+}
+
+/*
+ * The potential (per unit mass) a unit mass at d away contributes, softened and wrapped just like
+ * the force it exerts
+ */
+fn potential(d: vec2<f32>, cfg: &SimConfig) -> f32 {
+	let rsq = min_image(d, cfg.period).normsq() + cfg.eps * cfg.eps;
+	if rsq == 0.0 { 0.0 } else { -cfg.g / rsq.sqrt() }
+}
+
+/* Total gravitational potential energy, summed over each unordered pair once */
+pub fn potential_energy(state: &[Object], cfg: &SimConfig) -> f32 {
+	state.par_iter()
+		.enumerate()
+		.map(|(i, a)| -> f32 {
+			state.par_iter()
+				.skip(i + 1)
+				.map(|b| a.m * b.m * potential(b.s - a.s, cfg))
+				.sum()
+		})
+		.sum()
+}
+
+/* Total linear momentum of the system */
+pub fn total_momentum(state: &[Object]) -> vec2<f32> {
+	state.par_iter()
+		.map(|o| o.v * o.m)
+		.reduce(|| vec2::ZERO, |a, b| a + b)
+}
+
+/* Total angular momentum of the system about a point */
+pub fn angular_momentum_about(state: &[Object], at: vec2<f32>) -> f32 {
+	state.par_iter()
+		.map(|o| o.m * (o.s - at).cross(o.v))
+		.sum()
+}
+
+/* Total angular momentum of the system about the origin */
+pub fn angular_momentum(state: &[Object]) -> f32 {
+	angular_momentum_about(state, vec2::ZERO)
+}
+
+/* Total energy (and its parts), linear momentum, and angular momentum (about the origin) */
+#[derive(Clone, Copy, Debug)]
+pub struct Conserved {
+	pub ke: f32,
+	pub pe: f32,
+	pub e: f32,
+	pub p: vec2<f32>,
+	pub l: f32,
+}
+
+impl Conserved {
+	pub fn of(state: &[Object], cfg: &SimConfig) -> Conserved {
+		let (ke, pe) = (kinetic_energy(state), potential_energy(state, cfg));
+		Conserved {
+			ke,
+			pe,
+			e: ke + pe,
+			p: total_momentum(state),
+			l: angular_momentum(state),
+		}
+	}
+
+	/*
+	 * How far each quantity has drifted since init; relative for energy and angular momentum
+	 * (unless they started at zero), absolute for momentum
+	 */
+	pub fn drift(&self, init: &Conserved) -> (f32, f32, f32) {
+		let rel = |now: f32, then: f32| if then == 0.0 { now - then } else { (now - then) / then.abs() };
+		(rel(self.e, init.e), (self.p - init.p).norm(), rel(self.l, init.l))
+	}
+}
+
+/*
+ * Appends t,KE,PE,total,px,py,L along with the drift (as in Conserved::drift) since the first row
+ * to a CSV file, every so many steps
+ */
+pub struct EnergyLog {
+	out: BufWriter<File>,
+	every: usize,
+	count: usize,
+	init: Option<Conserved>,
+	last: Option<Conserved>,
+}
+
+impl EnergyLog {
+	pub fn create(path: &str) -> io::Result<EnergyLog> {
+		let mut out = BufWriter::new(File::create(path)?);
+		writeln!(out, "t,KE,PE,total,px,py,L,dE,dp,dL")?;
+		out.flush()?;
+		Ok(EnergyLog {
+			out,
+			every: 1,
+			count: 0,
+			init: None,
+			last: None,
+		})
+	}
+
+	/* Only write every nth call to record (the first is always written) */
+	pub fn every(&mut self, n: usize) {
+		self.every = n.max(1);
+	}
+
+	pub fn record(&mut self, t: f32, state: &[Object], cfg: &SimConfig) -> io::Result<()> {
+		self.count += 1;
+		if (self.count - 1) % self.every != 0 {
+			return Ok(());
+		}
+
+		let now = Conserved::of(state, cfg);
+		let init = *self.init.get_or_insert(now);
+		let (de, dp, dl) = now.drift(&init);
+		self.last = Some(now);
+		writeln!(self.out, "{},{},{},{},{},{},{},{},{},{}", t, now.ke, now.pe, now.e, now.p.x, now.p.y,
+			now.l, de, dp, dl)?;
+		/* Flush every row so the file is usable even if we are killed */
+		self.out.flush()
+	}
+
+	/* The drift as of the last row written, if any */
+	pub fn drift(&self) -> Option<(f32, f32, f32)> {
+		match (self.last, self.init) {
+			(Some(last), Some(init)) => Some(last.drift(&init)),
+			_ => None,
+		}
+	}
+}
+
+/* Appends t,body_index,x,y,vx,vy for every body to a CSV file after every step */
+pub struct TrajectoryLog {
+	out: BufWriter<File>,
+}
+
+impl TrajectoryLog {
+	pub fn create(path: &str) -> io::Result<TrajectoryLog> {
+		let mut out = BufWriter::new(File::create(path)?);
+		writeln!(out, "t,body_index,x,y,vx,vy")?;
+		out.flush()?;
+		Ok(TrajectoryLog { out })
+	}
+
+	pub fn record(&mut self, t: f32, state: &[Object]) -> io::Result<()> {
+		for (i, o) in state.iter().enumerate() {
+			writeln!(self.out, "{},{},{},{},{},{}", t, i, o.s.x, o.s.y, o.v.x, o.v.y)?;
+		}
+		/* Only flush whole steps, so a kill leaves at most one partial step behind */
+		self.out.flush()
+	}
+
+	pub fn flush(&mut self) -> io::Result<()> {
+		self.out.flush()
+	}
+}
+
+/* Histogram of body speeds, with bins evenly spread between zero and the fastest body */
+pub fn speed_histogram(state: &[Object], bins: usize) -> Vec<u32> {
+	let mut hist = vec![0; bins];
+	if bins == 0 {
+		return hist;
+	}
+
+	let speeds = state.iter()
+		.map(|o| o.v.norm::<f32>())
+		.collect::<Vec<f32>>();
+	let max = speeds.iter().cloned().fold(0.0, f32::max);
+	if max == 0.0 {
+		hist[0] = speeds.len() as u32;
+		return hist;
+	}
+
+	for v in speeds {
+		let i = ((v / max) * bins as f32) as usize;
+		hist[i.min(bins - 1)] += 1;
+	}
+	hist
+}
+
+/* Standard deviation of the body speeds about their mean */
+pub fn velocity_dispersion(state: &[Object]) -> f32 {
+	if state.is_empty() {
+		return 0.0;
+	}
+
+	let n = state.len() as f32;
+	let mean = state.par_iter()
+		.map(|o| o.v.norm::<f32>())
+		.sum::<f32>() / n;
+	let var = state.par_iter()
+		.map(|o| (o.v.norm::<f32>() - mean).powi(2))
+		.sum::<f32>() / n;
+	var.sqrt()
+}
+
+/*
+ * 2KE/|PE| from the kinetic and potential energies, which is about 1 for a system in virial
+ * equilibrium. Smaller values mean the system will collapse, and larger ones mean it will fly
+ * apart.
+ */
+pub fn virial_ratio(ke: f32, pe: f32) -> f32 {
+	if pe == 0.0 {
+		return ::std::f32::INFINITY;
+	}
+	2.0 * ke / pe.abs()
+}
+
+/*
+ * Scale every velocity so that the virial ratio comes out to target. Returns false (leaving the
+ * velocities alone) if nothing is moving or nothing is bound.
+ */
+pub fn scale_to_virial(state: &mut [Object], cfg: &SimConfig, target: f32) -> bool {
+	let (ke, pe) = (kinetic_energy(state), potential_energy(state, cfg));
+	if ke == 0.0 || pe == 0.0 {
+		return false;
+	}
+	let k = (target * pe.abs() / (2.0 * ke)).sqrt();
+	for o in state.iter_mut() {
+		o.v = o.v * k;
+	}
+	true
+}
+
+/*
+ * Gravitational potential (per unit mass) at a point, ignoring any body sitting exactly on it (such
+ * as the one it was asked about for)
+ */
+pub fn potential_at(at: vec2<f32>, state: &[Object], cfg: &SimConfig) -> f32 {
+	state.par_iter()
+		.filter(|o| (o.s - at).normsq() != 0.0)
+		.map(|o| o.m * potential(o.s - at, cfg))
+		.sum()
+}
+
+/*
+ * Whether a body's kinetic energy is too small for it to escape the rest of the system. The body's
+ * speed is taken relative to the rest of the system, which may well be drifting along with it.
+ */
+pub fn is_bound(body: &Object, state: &[Object], cfg: &SimConfig) -> bool {
+	let m = state.iter().map(|o| o.m).sum::<f32>() - body.m;
+	let v = if m > 0.0 {
+		body.v - (total_momentum(state) - body.v * body.m) * (1.0 / m)
+	} else {
+		body.v
+	};
+	bound_at(body.s, v, state, cfg)
+}
+
+/* Whether something at s moving at v (relative to the system) can't escape the system */
+fn bound_at(s: vec2<f32>, v: vec2<f32>, state: &[Object], cfg: &SimConfig) -> bool {
+	0.5 * v.normsq() + potential_at(s, state, cfg) < 0.0
+}
+
+/* Which bodies are further than dist from the barycenter */
+pub fn beyond(state: &[Object], dist: f32) -> Vec<bool> {
+	let com = center_of_mass(state);
+	state.par_iter()
+		.map(|o| (o.s - com).normsq() > dist * dist)
+		.collect()
+}
+
+/*
+ * The (ascending) indices of bodies which are leaving for good: further than dist from the
+ * barycenter, moving away from it, and unbound
+ */
+pub fn escaping(state: &[Object], cfg: &SimConfig, dist: f32) -> Vec<usize> {
+	let m = state.iter().map(|o| o.m).sum::<f32>();
+	if m == 0.0 {
+		return Vec::new();
+	}
+	let com = center_of_mass(state);
+	let vcom = total_momentum(state) * (1.0 / m);
+	let far = beyond(state, dist);
+	(0..state.len())
+		.filter(|&i| far[i])
+		.filter(|&i| (state[i].s - com).dot(state[i].v - vcom) > 0.0)
+		.filter(|&i| !bound_at(state[i].s, state[i].v - vcom, state, cfg))
+		.collect()
+}
+
+/* Speed needed to escape the whole system's potential from a point */
+pub fn escape_velocity(at: vec2<f32>, state: &[Object], cfg: &SimConfig) -> f32 {
+	(-2.0 * potential_at(at, state, cfg)).max(0.0).sqrt()
+}
+
+/* The lower-left and upper-right corners of the smallest box containing every body */
+pub fn bounding_box(state: &[Object]) -> (vec2<f32>, vec2<f32>) {
+	match state.first() {
+		Some(first) => state.iter()
+			.fold((first.s, first.s), |(lo, hi), o| (lo.min(o.s), hi.max(o.s))),
+		None => (vec2::new(0.0, 0.0), vec2::new(0.0, 0.0)),
+	}
+}
+
+/* Mass-weighted mean position of the system; the origin if there is no mass at all */
+pub fn center_of_mass(state: &[Object]) -> vec2<f32> {
+	let m = state.par_iter()
+		.map(|o| o.m)
+		.sum::<f32>();
+	if m == 0.0 {
+		return vec2::ZERO;
+	}
+	state.par_iter()
+		.map(|o| o.s * o.m)
+		.reduce(|| vec2::ZERO, |a, b| a + b) * (1.0 / m)
+}
+
+/* Shift every velocity so the system as a whole stays put; single bodies are left alone */
+pub fn recenter_momentum(state: &mut [Object]) {
+	let m = state.iter().map(|o| o.m).sum::<f32>();
+	if state.len() < 2 || m == 0.0 {
+		return;
+	}
+	let v = total_momentum(state) * (1.0 / m);
+	for o in state.iter_mut() {
+		o.v = o.v - v;
+	}
+}
+
+/* Shift every position so the center of mass sits at the origin */
+pub fn recenter_position(state: &mut [Object]) {
+	if state.len() < 2 {
+		return;
+	}
+	let c = center_of_mass(state);
+	for o in state.iter_mut() {
+		o.s = o.s - c;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::fs;
+	use std::env;
+
+	use orbit::circular_binary;
+	use random::plummer;
+
+	fn body(x: f32, y: f32, vx: f32, vy: f32, m: f32) -> Object {
+		Object {
+			s: vec2::new(x, y),
+			v: vec2::new(vx, vy),
+			m,
+			r: 0.0,
+			fixed: false,
+		}
+	}
+
+	fn unit() -> SimConfig {
+		SimConfig {
+			g: 1.0,
+			..SimConfig::default()
+		}
+	}
+
+	fn close(a: f32, b: f32, tol: f32) -> bool {
+		(a - b).abs() <= tol * b.abs().max(1.0e-30)
+	}
+
+	fn temp(name: &str) -> String {
+		env::temp_dir().join(name).to_str().unwrap().to_string()
+	}
+
+	#[test]
+	fn pair_potential() {
+		let state = [body(0.0, 0.0, 0.0, 0.0, 2.0), body(4.0, 0.0, 0.0, 0.0, 3.0)];
+		assert!(close(potential_energy(&state, &unit()), -1.5, 1.0e-6));
+
+		let soft = SimConfig { eps: 3.0, ..unit() };
+		assert!(close(potential_energy(&state, &soft), -1.2, 1.0e-6));
+
+		/* The nearest image is 2 away the other way around */
+		let wrapped = SimConfig { period: Some(6.0), ..unit() };
+		assert!(close(potential_energy(&state, &wrapped), -3.0, 1.0e-6));
+	}
+
+	#[test]
+	fn momenta() {
+		let state = [body(3.0, 0.0, 0.0, 5.0, 2.0), body(0.0, 1.0, 1.0, 0.0, 1.0)];
+		assert!(close(angular_momentum(&state), 29.0, 1.0e-6));
+		assert!(close(angular_momentum_about(&state[..1], vec2::new(3.0, -1.0)), 0.0, 1.0e-6));
+		let p = total_momentum(&state);
+		assert_eq!((p.x, p.y), (1.0, 10.0));
+	}
+
+	#[test]
+	fn recentering() {
+		let mut state = plummer(50, 1.0, 1.0, 1.0, 3);
+		for o in state.iter_mut() {
+			o.s = o.s + vec2::new(10.0, -4.0);
+			o.v = o.v + vec2::new(0.5, 0.25);
+		}
+		recenter_momentum(&mut state);
+		recenter_position(&mut state);
+		assert!(total_momentum(&state).norm::<f32>() < 1.0e-5);
+		assert!(center_of_mass(&state).norm::<f32>() < 1.0e-4);
+
+		/* A lone body keeps its motion */
+		let mut lone = [body(1.0, 1.0, 2.0, 0.0, 1.0)];
+		recenter_momentum(&mut lone);
+		assert_eq!(lone[0].v.x, 2.0);
+	}
+
+	#[test]
+	fn dispersion() {
+		let state = [body(0.0, 0.0, 1.0, 0.0, 1.0), body(1.0, 0.0, 0.0, -3.0, 1.0)];
+		assert!(close(velocity_dispersion(&state), 1.0, 1.0e-6));
+		assert_eq!(velocity_dispersion(&[]), 0.0);
+		assert_eq!(speed_histogram(&state, 3), vec![0, 1, 1]);
+	}
+
+	#[test]
+	fn virial() {
+		let ratio = |state: &[Object]| {
+			virial_ratio(kinetic_energy(state), potential_energy(state, &unit()))
+		};
+		let (state, _) = circular_binary(1.0, 1.0, 1.0);
+		assert!(close(ratio(state.as_slice()), 1.0, 1.0e-5));
+
+		let mut state = plummer(200, 1.0, 1.0, 1.0, 11);
+		assert!(scale_to_virial(state.as_mut_slice(), &unit(), 0.5));
+		assert!(close(ratio(state.as_slice()), 0.5, 1.0e-4));
+
+		let mut still = [body(0.0, 0.0, 0.0, 0.0, 1.0), body(1.0, 0.0, 0.0, 0.0, 1.0)];
+		assert!(!scale_to_virial(&mut still, &unit(), 1.0));
+	}
+
+	#[test]
+	fn escape() {
+		let sun = body(0.0, 0.0, 0.0, 0.0, 1.0);
+		let at = vec2::new(4.0, 0.0);
+		let v = escape_velocity(at, &[sun], &unit());
+		assert!(close(v, 0.5f32.sqrt(), 1.0e-6));
+
+		let slow = [sun, body(4.0, 0.0, 0.99 * v, 0.0, 0.0)];
+		let fast = [sun, body(4.0, 0.0, 1.01 * v, 0.0, 0.0)];
+		let falling = [sun, body(4.0, 0.0, -1.01 * v, 0.0, 0.0)];
+		assert!(is_bound(&slow[1], &slow, &unit()));
+		assert!(!is_bound(&fast[1], &fast, &unit()));
+
+		assert!(escaping(&slow, &unit(), 2.0).is_empty());
+		assert_eq!(escaping(&fast, &unit(), 2.0), vec![1]);
+		assert!(escaping(&fast, &unit(), 8.0).is_empty());
+		assert!(escaping(&falling, &unit(), 2.0).is_empty());
+	}
+
+	/* A pair drifting along together is just as bound as one sitting still */
+	#[test]
+	fn bound_while_drifting() {
+		let v = escape_velocity(vec2::new(4.0, 0.0), &[body(0.0, 0.0, 0.0, 0.0, 1.0)], &unit());
+		let drift = 100.0 * v;
+		let slow = [body(0.0, 0.0, 0.0, drift, 1.0), body(4.0, 0.0, 0.99 * v, drift, 0.0)];
+		let fast = [body(0.0, 0.0, 0.0, drift, 1.0), body(4.0, 0.0, 1.01 * v, drift, 0.0)];
+		assert!(is_bound(&slow[1], &slow, &unit()));
+		assert!(!is_bound(&fast[1], &fast, &unit()));
+
+		/* Both bodies count towards the drift when they have similar masses */
+		let pair = [body(-0.5, 0.0, drift, -0.5, 1.0), body(0.5, 0.0, drift, 0.5, 1.0)];
+		assert!(is_bound(&pair[0], &pair, &unit()));
+		assert!(is_bound(&pair[1], &pair, &unit()));
+
+		assert!(escaping(&slow, &unit(), 2.0).is_empty());
+		assert_eq!(escaping(&fast, &unit(), 2.0), vec![1]);
+	}
+
+	#[test]
+	fn bounds() {
+		let corners = |state: &[Object]| {
+			let (lo, hi) = bounding_box(state);
+			(lo.x, lo.y, hi.x, hi.y)
+		};
+		assert_eq!(corners(&[]), (0.0, 0.0, 0.0, 0.0));
+		assert_eq!(corners(&[body(3.0, -2.0, 1.0, 1.0, 1.0)]), (3.0, -2.0, 3.0, -2.0));
+		let same = [body(1.5, 2.5, 0.0, 0.0, 1.0), body(1.5, 2.5, 1.0, 0.0, 2.0)];
+		assert_eq!(corners(&same), (1.5, 2.5, 1.5, 2.5));
+		let mixed = [
+			body(-1.0, 4.0, 0.0, 0.0, 1.0),
+			body(2.0, -3.0, 0.0, 0.0, 1.0),
+			body(-5.0, -0.5, 0.0, 0.0, 0.0),
+			body(0.5, 1.0, 0.0, 0.0, 1.0),
+		];
+		assert_eq!(corners(&mixed), (-5.0, -3.0, 2.0, 4.0));
+	}
+
+	#[test]
+	fn energy_log_rows() {
+		let path = temp("nbody-test-energy.csv");
+		let (state, _) = circular_binary(1.0, 1.0, 1.0);
+		{
+			let mut log = EnergyLog::create(&path).unwrap();
+			assert!(log.drift().is_none());
+			log.every(3);
+			for k in 0..10 {
+				log.record(k as f32, state.as_slice(), &unit()).unwrap();
+			}
+			assert_eq!(log.drift(), Some((0.0, 0.0, 0.0)));
+		}
+		let csv = fs::read_to_string(&path).unwrap();
+		fs::remove_file(&path).unwrap();
+
+		let rows = csv.lines().collect::<Vec<_>>();
+		assert_eq!(rows[0], "t,KE,PE,total,px,py,L,dE,dp,dL");
+		/* Calls 0, 3, 6 and 9 */
+		assert_eq!(rows.len(), 5);
+		assert!(rows[4].starts_with("9,"));
+	}
+
+	#[test]
+	fn trajectory_log_round_trip() {
+		let path = temp("nbody-test-trajectory.csv");
+		let state = [body(1.0, 2.0, 3.0, 4.0, 1.0), body(-1.5, 0.25, 0.0, -2.0, 1.0)];
+		{
+			let mut log = TrajectoryLog::create(&path).unwrap();
+			for k in 0..3 {
+				log.record(k as f32, &state).unwrap();
+			}
+		}
+		let csv = fs::read_to_string(&path).unwrap();
+		fs::remove_file(&path).unwrap();
+
+		let rows = csv.lines().collect::<Vec<_>>();
+		assert_eq!(rows.len(), 1 + 3 * state.len());
+		let last = rows[6].split(',')
+			.map(|f| f.parse::<f32>().unwrap())
+			.collect::<Vec<_>>();
+		assert_eq!(last, vec![2.0, 1.0, -1.5, 0.25, 0.0, -2.0]);
+	}
+}
@@ -0,0 +1,345 @@
+use std::str::FromStr;
+
+use rayon::prelude::*;
+
+use math::vec2;
+use {min_image, remap, Object};
+
+/*
+ * Something which accelerates bodies on top of (or instead of) mutual Newtonian gravity. Gravity
+ * itself has its own solvers, since it's the one force worth approximating.
+ */
+pub trait Force: Sync {
+	/* The acceleration of state[i] */
+	fn accel(&self, state: &[Object], i: usize) -> vec2<f32>;
+}
+
+/* Add the acceleration from each of forces to acc */
+pub fn apply(forces: &[Box<Force>], state: &[Object], acc: Vec<vec2<f32>>) -> Vec<vec2<f32>> {
+	if forces.is_empty() {
+		return acc;
+	}
+	acc.into_par_iter()
+		.enumerate()
+		.map(|(i, a)| forces.iter().fold(a, |a, f| a + f.accel(state, i)))
+		.collect()
+}
+
+/*
+ * The first-order post-Newtonian correction to gravity, treating every other body as a point mass
+ * with a's orbit about it in the test-particle limit (enough for e.g. Mercury's perihelion
+ * precession)
+ */
+pub struct PostNewtonian {
+	pub g: f32,
+	/* The speed of light */
+	pub c: f32,
+	pub period: Option<f32>,
+}
+
+impl Force for PostNewtonian {
+	fn accel(&self, state: &[Object], i: usize) -> vec2<f32> {
+		let a = &state[i];
+		state.iter()
+			.filter(|b| b.m != 0.0)
+			.fold(vec2::ZERO, |acc, b| {
+				let r = min_image(a.s - b.s, self.period);
+				let rsq = r.normsq();
+				if rsq == 0.0 {
+					return acc;
+				}
+				let d = rsq.sqrt();
+				let v = a.v - b.v;
+				let gm = self.g * b.m;
+				let k = gm / (self.c * self.c * rsq * d);
+				acc + (r * (4.0 * gm / d - v.normsq()) + v * (4.0 * r.dot(v))) * k
+			})
+	}
+}
+
+/*
+ * Drag through a medium moving with (and, if given, thinning out away from) a central body:
+ * linear * v + quadratic * |v| v, against the velocity relative to it
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Drag {
+	pub linear: f32,
+	pub quadratic: f32,
+	/* The body the medium surrounds, if any */
+	#[serde(default)]
+	pub center: Option<usize>,
+	/* The density falls off by a factor of e every this far from the center */
+	#[serde(default)]
+	pub scale: Option<f32>,
+}
+
+impl Drag {
+	/* Follow the center through bodies being removed; there's no medium left if it went too */
+	pub fn remove(self, removed: &[usize]) -> Option<Drag> {
+		match self.center {
+			Some(c) => remap(c, removed).map(|c| Drag { center: Some(c), ..self }),
+			None => Some(self),
+		}
+	}
+}
+
+/* "linear quadratic [center scale]" */
+impl FromStr for Drag {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<Drag, ()> {
+		let toks = s.split_whitespace().collect::<Vec<_>>();
+		let num = |i: usize| toks[i].parse::<f32>().map_err(|_| ());
+		match toks.len() {
+			2 => Ok(Drag {
+				linear: num(0)?,
+				quadratic: num(1)?,
+				center: None,
+				scale: None,
+			}),
+			4 => Ok(Drag {
+				linear: num(0)?,
+				quadratic: num(1)?,
+				center: Some(toks[2].parse().map_err(|_| ())?),
+				scale: Some(num(3)?),
+			}),
+			_ => Err(()),
+		}
+	}
+}
+
+impl Force for Drag {
+	fn accel(&self, state: &[Object], i: usize) -> vec2<f32> {
+		let a = &state[i];
+		let center = self.center.and_then(|c| state.get(c));
+		let (v, density) = match center {
+			Some(c) => {
+				let density = self.scale.map_or(1.0, |h| (-(a.s - c.s).norm::<f32>() / h).exp());
+				(a.v - c.v, density)
+			},
+			None => (a.v, 1.0),
+		};
+		v * (-density * (self.linear + self.quadratic * v.norm::<f32>()))
+	}
+}
+
+/* A fixed potential centered on the origin, for bodies to orbit without simulating its source */
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Background {
+	Point { m: f32 },
+	/* A uniform-density core, pulling with omega^2 r */
+	Harmonic { omega: f32 },
+	/* A dark matter halo with mass parameter 4 pi rho_0 rs^3 and scale radius rs */
+	Nfw { m: f32, rs: f32 },
+	/* A disk with scale length a and scale height b, seen in its midplane */
+	MiyamotoNagai { m: f32, a: f32, b: f32 },
+}
+
+/* "point M", "harmonic OMEGA", "nfw M RS", or "miyamoto-nagai M A B" */
+impl FromStr for Background {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<Background, ()> {
+		let toks = s.split_whitespace().collect::<Vec<_>>();
+		let nums = toks.iter()
+			.skip(1)
+			.map(|t| t.parse::<f32>().map_err(|_| ()))
+			.collect::<Result<Vec<_>, ()>>()?;
+		match (toks.first().cloned(), nums.len()) {
+			(Some("point"), 1) => Ok(Background::Point { m: nums[0] }),
+			(Some("harmonic"), 1) => Ok(Background::Harmonic { omega: nums[0] }),
+			(Some("nfw"), 2) => Ok(Background::Nfw { m: nums[0], rs: nums[1] }),
+			(Some("miyamoto-nagai"), 3) =>
+				Ok(Background::MiyamotoNagai { m: nums[0], a: nums[1], b: nums[2] }),
+			_ => Err(()),
+		}
+	}
+}
+
+pub struct External {
+	pub potential: Background,
+	pub g: f32,
+}
+
+impl Force for External {
+	fn accel(&self, state: &[Object], i: usize) -> vec2<f32> {
+		let s = state[i].s;
+		let rsq = s.normsq();
+		if rsq == 0.0 {
+			return vec2::ZERO;
+		}
+		let r = rsq.sqrt();
+		/* Everything but the harmonic core is GM(<r) / r^2 towards the origin */
+		let gm = match self.potential {
+			Background::Point { m } => self.g * m,
+			Background::Harmonic { omega } => return s * -(omega * omega),
+			Background::Nfw { m, rs } => {
+				let x = r / rs;
+				self.g * m * ((1.0 + x).ln() - x / (1.0 + x))
+			},
+			Background::MiyamotoNagai { m, a, b } => {
+				let dsq = rsq + (a + b) * (a + b);
+				self.g * m * rsq * r / (dsq * dsq.sqrt())
+			},
+		};
+		s * (-gm / (rsq * r))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use SimConfig;
+
+	fn body(x: f32, y: f32, vx: f32, vy: f32) -> Object {
+		Object {
+			s: vec2::new(x, y),
+			v: vec2::new(vx, vy),
+			m: 1.0,
+			r: 0.0,
+			fixed: false,
+		}
+	}
+
+	#[test]
+	fn drag() {
+		let drag = "0.5 0.25".parse::<Drag>().unwrap();
+		let a = drag.accel(&[body(0.0, 0.0, 2.0, 0.0)], 0);
+		assert_eq!((a.x, a.y), (-2.0, 0.0));
+
+		/* Relative to the center, and thinner away from it */
+		let drag = "1 0 1 10".parse::<Drag>().unwrap();
+		let state = [body(10.0, 0.0, 1.0, 3.0), body(0.0, 0.0, 1.0, 2.0)];
+		let a = drag.accel(&state, 0);
+		assert!(a.x == 0.0 && (a.y + (-1.0f32).exp()).abs() < 1.0e-6, "{:?}", a);
+
+		assert!("1 2 3".parse::<Drag>().is_err());
+		assert!("fast 2".parse::<Drag>().is_err());
+	}
+
+	#[test]
+	fn drag_follows_its_center() {
+		let drag = "1 0 3 10".parse::<Drag>().unwrap();
+		assert_eq!(drag.remove(&[1, 4]).unwrap().center, Some(2));
+		assert_eq!(drag.remove(&[4]).unwrap().center, Some(3));
+		assert!(drag.remove(&[0, 3]).is_none());
+		let uniform = "1 0".parse::<Drag>().unwrap();
+		assert_eq!(uniform.remove(&[0]), Some(uniform));
+	}
+
+	/* A test particle on a circular orbit only feels an extra radial push */
+	#[test]
+	fn post_newtonian_circular() {
+		const C: f32 = 10.0;
+		let pn = PostNewtonian { g: 1.0, c: C, period: None };
+		let (r, v) = (2.0f32, 0.5f32.sqrt());
+		let particle = Object { m: 0.0, ..body(r, 0.0, 0.0, v) };
+		let a = pn.accel(&[body(0.0, 0.0, 0.0, 0.0), particle], 1);
+		let radial = (4.0 / r - v * v) / (C * C * r * r);
+		assert!((a.x - radial).abs() < 1.0e-6 * radial && a.y == 0.0, "{:?} vs {}", a, radial);
+	}
+
+	/* Periapsis advances by 6 pi GM / (c^2 a (1 - e^2)) each orbit */
+	#[test]
+	fn post_newtonian_precession() {
+		const C: f32 = 40.0;
+		const STEPS: usize = 5000;
+		let pn = PostNewtonian { g: 1.0, c: C, period: None };
+		let sun = body(0.0, 0.0, 0.0, 0.0);
+		let accel = |s: vec2<f32>, v: vec2<f32>| {
+			let particle = Object { m: 0.0, ..body(s.x, s.y, v.x, v.y) };
+			s * (-1.0 / (s.normsq() * s.normsq().sqrt())) + pn.accel(&[sun, particle], 1)
+		};
+
+		/* Starting from periapsis with a = 1 and e = 0.5 */
+		let (a, e) = (1.0f32, 0.5f32);
+		let mut s = vec2::new(a * (1.0 - e), 0.0);
+		let mut v = vec2::new(0.0, ((1.0 + e) / (a * (1.0 - e))).sqrt());
+		let h = 2.0 * ::std::f32::consts::PI / STEPS as f32;
+		let mut outbound = true;
+		let mut shift = None;
+		for _ in 0..2 * STEPS {
+			let (s0, v0) = (s, v);
+			let (k1s, k1v) = (v, accel(s, v));
+			let (k2s, k2v) = (v + k1v * (0.5 * h), accel(s + k1s * (0.5 * h), v + k1v * (0.5 * h)));
+			let (k3s, k3v) = (v + k2v * (0.5 * h), accel(s + k2s * (0.5 * h), v + k2v * (0.5 * h)));
+			let (k4s, k4v) = (v + k3v * h, accel(s + k3s * h, v + k3v * h));
+			s = s + (k1s + k2s * 2.0 + k3s * 2.0 + k4s) * (h / 6.0);
+			v = v + (k1v + k2v * 2.0 + k3v * 2.0 + k4v) * (h / 6.0);
+
+			/* Back at periapsis once r.v goes from negative to positive */
+			let (before, after) = (s0.dot(v0), s.dot(v));
+			if before > 0.0 && after <= 0.0 {
+				outbound = false;
+			} else if !outbound && before < 0.0 && after >= 0.0 {
+				let k = before / (before - after);
+				let angle = |s: vec2<f32>| s.y.atan2(s.x);
+				shift = Some(angle(s0) + (angle(s) - angle(s0)) * k);
+				break;
+			}
+		}
+
+		let want = 6.0 * ::std::f32::consts::PI / (C * C * a * (1.0 - e * e));
+		let shift = shift.expect("never came back to periapsis");
+		assert!((shift - want).abs() < 0.02 * want, "{} vs {}", shift, want);
+	}
+
+	#[test]
+	fn backgrounds() {
+		let pull = |potential: Background, at: &Object| {
+			External { potential, g: 2.0 }.accel(&[*at], 0)
+		};
+
+		let point = "point 3".parse().unwrap();
+		let a = pull(point, &body(0.0, 2.0, 0.0, 0.0));
+		assert!(a.x == 0.0 && (a.y + 1.5).abs() < 1.0e-6, "{:?}", a);
+		assert_eq!(pull(point, &body(0.0, 0.0, 0.0, 0.0)).y, 0.0);
+
+		let a = pull("harmonic 2".parse().unwrap(), &body(1.0, -1.0, 0.0, 0.0));
+		assert_eq!((a.x, a.y), (-4.0, 4.0));
+
+		assert_eq!("nfw 1 2".parse::<Background>(), Ok(Background::Nfw { m: 1.0, rs: 2.0 }));
+		assert_eq!("miyamoto-nagai 1 2 3".parse::<Background>(),
+			Ok(Background::MiyamotoNagai { m: 1.0, a: 2.0, b: 3.0 }));
+		assert!("point".parse::<Background>().is_err());
+		assert!("plummer 1".parse::<Background>().is_err());
+	}
+
+	#[test]
+	fn background_profiles() {
+		let pull = |potential: Background, at: &Object| {
+			External { potential, g: 2.0 }.accel(&[*at], 0)
+		};
+
+		/* At the scale radius, NFW has ln 2 - 1/2 of its mass parameter inside */
+		let a = pull(Background::Nfw { m: 3.0, rs: 2.0 }, &body(0.0, 2.0, 0.0, 0.0));
+		let want = 2.0 * 3.0 * (2.0f32.ln() - 0.5) / 4.0;
+		assert!(a.x == 0.0 && (a.y + want).abs() < 1.0e-6, "{:?} vs {}", a, want);
+
+		/* A Miyamoto-Nagai disk with no radial scale is a Plummer sphere */
+		let disk = Background::MiyamotoNagai { m: 3.0, a: 0.0, b: 1.5 };
+		for &r in &[0.5f32, 1.5, 4.0] {
+			let a = pull(disk, &body(r, 0.0, 0.0, 0.0));
+			let want = 2.0 * 3.0 * r / (r * r + 1.5 * 1.5).powf(1.5);
+			assert!(a.y == 0.0 && (a.x + want).abs() < 1.0e-5 * want, "{:?} vs {}", a, want);
+		}
+	}
+
+	#[test]
+	fn no_gravity_no_gravitational_forces() {
+		let cfg = SimConfig {
+			c: Some(3.0e8),
+			drag: Some("1 0".parse().unwrap()),
+			background: Some("point 1".parse().unwrap()),
+			..SimConfig::default()
+		};
+		assert_eq!(cfg.forces().len(), 3);
+		/* Only drag doesn't come from gravity */
+		let forces = SimConfig { gravity: false, ..cfg }.forces();
+		assert_eq!(forces.len(), 1);
+		let a = forces[0].accel(&[body(0.0, 1.0, 1.0, 0.0)], 0);
+		assert_eq!((a.x, a.y), (-1.0, 0.0));
+	}
+}
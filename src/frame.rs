@@ -0,0 +1,66 @@
+use math::{mat2, vec2};
+
+/* The reference frame bodies are drawn in; the physics always happens in the inertial frame */
+#[derive(Clone, Copy, Debug)]
+pub struct Frame {
+	/* World position drawn at the origin */
+	pub origin: vec2<f32>,
+	/* How far the frame has rotated (counterclockwise) relative to the world */
+	pub angle: f32,
+}
+
+fn rotate(p: vec2<f32>, angle: f32) -> vec2<f32> {
+	mat2::rotation(angle) * p
+}
+
+impl Frame {
+	pub fn inertial() -> Frame {
+		Frame {
+			origin: vec2::new(0.0, 0.0),
+			angle: 0.0,
+		}
+	}
+
+	/* Map a world position to where it should be drawn */
+	pub fn apply(&self, p: vec2<f32>) -> vec2<f32> {
+		rotate(p - self.origin, -self.angle)
+	}
+
+	/* Map a drawn position back to the world */
+	pub fn unapply(&self, p: vec2<f32>) -> vec2<f32> {
+		rotate(p, self.angle) + self.origin
+	}
+
+	/* Map a drawn direction (e.g. "up") back to the world */
+	pub fn unapply_dir(&self, d: vec2<f32>) -> vec2<f32> {
+		rotate(d, self.angle)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::f32::consts::PI;
+
+	fn close(a: vec2<f32>, b: vec2<f32>) -> bool {
+		(a - b).normsq() < 1.0e-10
+	}
+
+	#[test]
+	fn round_trip() {
+		let frame = Frame {
+			origin: vec2::new(3.0, -1.0),
+			angle: PI / 2.0,
+		};
+		/* A quarter turn of the frame turns what's drawn a quarter turn back */
+		assert!(close(frame.apply(vec2::new(3.0, 1.0)), vec2::new(2.0, 0.0)));
+		for &p in [vec2::new(0.0, 0.0), vec2::new(5.0, 2.0), vec2::new(-7.0, 0.5)].iter() {
+			assert!(close(frame.unapply(frame.apply(p)), p));
+		}
+		assert!(close(frame.unapply_dir(vec2::new(0.0, 1.0)), vec2::new(-1.0, 0.0)));
+
+		let p = vec2::new(4.0, 4.0);
+		assert!(close(Frame::inertial().apply(p), p));
+	}
+}
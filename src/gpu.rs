@@ -0,0 +1,208 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use pollster;
+use wgpu;
+use wgpu::util::DeviceExt;
+
+use math::vec2;
+use profile::time_forces;
+use force::apply;
+use {Deriv, Object, SimConfig, cached_accelerations, diff, partials, to_derivs};
+
+const SHADER: &'static str = "
+struct Params {
+	n: u32,
+	g: f32,
+	epssq: f32,
+	period: f32,
+}
+
+@group(0) @binding(0) var<storage, read> bodies: array<vec4<f32>>;
+@group(0) @binding(1) var<storage, read_write> acc: array<vec2<f32>>;
+@group(0) @binding(2) var<uniform> params: Params;
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+	let i = id.x;
+	if (i >= params.n) {
+		return;
+	}
+
+	let a = bodies[i].xy;
+	var sum = vec2<f32>(0.0, 0.0);
+	for (var j = 0u; j < params.n; j = j + 1u) {
+		if (j == i) {
+			continue;
+		}
+		var ba = bodies[j].xy - a;
+		if (params.period > 0.0) {
+			ba = ba - params.period * round(ba / params.period);
+		}
+		let rsq = dot(ba, ba) + params.epssq;
+		sum = sum + ba * (params.g * bodies[j].z / (rsq * sqrt(rsq)));
+	}
+	acc[i] = sum;
+}
+";
+
+struct Gpu {
+	device: wgpu::Device,
+	queue: wgpu::Queue,
+	pipeline: wgpu::ComputePipeline,
+}
+
+thread_local!(static GPU: RefCell<Option<Option<Gpu>>> = RefCell::new(None));
+
+impl Gpu {
+	fn new() -> Option<Gpu> {
+		let instance = wgpu::Instance::default();
+		let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+			power_preference: wgpu::PowerPreference::HighPerformance,
+			..Default::default()
+		}))?;
+		let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+			label: None,
+			required_features: wgpu::Features::empty(),
+			required_limits: wgpu::Limits::downlevel_defaults(),
+		}, None)).ok()?;
+
+		let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+			label: Some("grav"),
+			source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(SHADER)),
+		});
+		let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+			label: Some("grav"),
+			layout: None,
+			module: &module,
+			entry_point: "main",
+		});
+
+		Some(Gpu { device, queue, pipeline })
+	}
+
+	/* The gravitational acceleration of every body due to every other body */
+	fn accelerations(&self, state: &[Object], cfg: &SimConfig) -> Vec<vec2<f32>> {
+		let n = state.len();
+		let bodies = state.iter()
+			.flat_map(|o| vec![o.s.x, o.s.y, o.m, 0.0])
+			.flat_map(|f| f.to_ne_bytes().to_vec())
+			.collect::<Vec<u8>>();
+		let params = [(n as u32).to_ne_bytes(), cfg.g.to_ne_bytes(), (cfg.eps * cfg.eps).to_ne_bytes(),
+			cfg.period.unwrap_or(0.0).to_ne_bytes()].concat();
+		let size = (n * 2 * 4) as wgpu::BufferAddress;
+
+		let bodies = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+			label: Some("bodies"),
+			contents: bodies.as_slice(),
+			usage: wgpu::BufferUsages::STORAGE,
+		});
+		let params = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+			label: Some("params"),
+			contents: params.as_slice(),
+			usage: wgpu::BufferUsages::UNIFORM,
+		});
+		let acc = self.device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("acc"),
+			size: size,
+			usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+			mapped_at_creation: false,
+		});
+		let staging = self.device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("staging"),
+			size: size,
+			usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+			mapped_at_creation: false,
+		});
+
+		let layout = self.pipeline.get_bind_group_layout(0);
+		let group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: None,
+			layout: &layout,
+			entries: &[
+				wgpu::BindGroupEntry { binding: 0, resource: bodies.as_entire_binding() },
+				wgpu::BindGroupEntry { binding: 1, resource: acc.as_entire_binding() },
+				wgpu::BindGroupEntry { binding: 2, resource: params.as_entire_binding() },
+			],
+		});
+
+		let mut encoder = self.device
+			.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+		{
+			let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+				label: None,
+				timestamp_writes: None,
+			});
+			pass.set_pipeline(&self.pipeline);
+			pass.set_bind_group(0, &group, &[]);
+			pass.dispatch_workgroups(((n + 63) / 64) as u32, 1, 1);
+		}
+		encoder.copy_buffer_to_buffer(&acc, 0, &staging, 0, size);
+		self.queue.submit(Some(encoder.finish()));
+
+		let slice = staging.slice(..);
+		slice.map_async(wgpu::MapMode::Read, |_| ());
+		self.device.poll(wgpu::Maintain::Wait);
+		let acc = {
+			let data = slice.get_mapped_range();
+			data.chunks(8)
+				.map(|c| vec2::new(f32::from_ne_bytes([c[0], c[1], c[2], c[3]]),
+					f32::from_ne_bytes([c[4], c[5], c[6], c[7]])))
+				.collect()
+		};
+		staging.unmap();
+		acc
+	}
+}
+
+/* Like diff, but with the accelerations calculated on the GPU. Falls back to diff without one. */
+pub fn diff_gpu(init: &[Object], t: f32, dt: f32, derivs: &[Deriv], cfg: &SimConfig) -> Vec<Deriv> {
+	if !cfg.gravity || init.is_empty() {
+		return diff(init, t, dt, derivs, cfg);
+	}
+
+	GPU.with(|gpu| {
+		let mut gpu = gpu.borrow_mut();
+		if gpu.is_none() {
+			*gpu = Some(Gpu::new());
+			if gpu.as_ref().unwrap().is_none() {
+				eprintln!("no GPU available; falling back to the CPU");
+			}
+		}
+
+		match *gpu.as_ref().unwrap() {
+			Some(ref gpu) => {
+				let new = partials(init, derivs, dt);
+				let acc = cached_accelerations(new.as_slice(), cfg,
+					|| time_forces(|| gpu.accelerations(new.as_slice(), cfg)));
+				let acc = apply(cfg.forces().as_slice(), new.as_slice(), acc);
+				to_derivs(new.as_slice(), acc.as_slice())
+			},
+			None => diff(init, t, dt, derivs, cfg),
+		}
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use accelerations as pairwise;
+	use testing::{cluster, max_error};
+
+	#[test]
+	fn matches_pairwise() {
+		/* Nothing to compare against without a GPU */
+		let gpu = match Gpu::new() {
+			Some(gpu) => gpu,
+			None => return,
+		};
+		let state = cluster(300);
+		for &period in [None, Some(3.0e11)].iter() {
+			let cfg = SimConfig { period, ..SimConfig::default() };
+			let err = max_error(gpu.accelerations(state.as_slice(), &cfg).as_slice(),
+				pairwise(state.as_slice(), &cfg).as_slice());
+			assert!(err < 1.0e-4, "error {}", err);
+		}
+	}
+}
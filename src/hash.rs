@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+
+use diag::bounding_box;
+use math::vec2;
+use Object;
+
+/* The most cells across a body may cover before it's kept out of the grid */
+const MAX_SPAN: f32 = 4.0;
+
+/*
+ * A uniform grid bucketing bodies by the cells their bounding boxes cover. Two bodies can only
+ * touch if they share a cell, so bodies just go in every cell they overlap. Bodies too big for
+ * that are kept to one side, and checked against everything.
+ */
+pub struct SpatialHash {
+	cell: f32,
+	/* Where cell (0, 0) starts, so cell indices stay small wherever the bodies are */
+	origin: vec2<f32>,
+	cells: HashMap<(i32, i32), Vec<usize>>,
+	big: Vec<usize>,
+	all: Vec<usize>,
+}
+
+impl SpatialHash {
+	pub fn new(cell: f32, origin: vec2<f32>) -> SpatialHash {
+		SpatialHash {
+			cell,
+			origin,
+			cells: HashMap::new(),
+			big: Vec::new(),
+			all: Vec::new(),
+		}
+	}
+
+	/*
+	 * A hash with about one body per cell over the area they're spread across, or cells big enough
+	 * for a typical body to fit in one, whichever is larger
+	 */
+	pub fn build(state: &[Object]) -> SpatialHash {
+		let (lo, hi) = bounding_box(state);
+		let extent = (hi.x - lo.x).max(hi.y - lo.y);
+		let spacing = extent / (state.len() as f32).sqrt();
+		let sized = state.iter().filter(|o| o.r > 0.0).collect::<Vec<_>>();
+		let typical = if sized.is_empty() {
+			0.0
+		} else {
+			2.0 * sized.iter().map(|o| o.r).sum::<f32>() / sized.len() as f32
+		};
+		/* Everything in one spot (or nothing at all) can go in one cell of any size */
+		let cell = match spacing.max(typical) {
+			cell if cell > 0.0 && cell.is_finite() => cell,
+			_ => 1.0,
+		};
+
+		let mut hash = SpatialHash::new(cell, lo);
+		for (i, o) in state.iter().enumerate() {
+			hash.insert(i, o.s, o.r);
+		}
+		hash
+	}
+
+	fn too_big(&self, r: f32) -> bool {
+		2.0 * r > MAX_SPAN * self.cell
+	}
+
+	/* The (inclusive) range of cells covered by a circle no bigger than MAX_SPAN cells */
+	fn span(&self, s: vec2<f32>, r: f32) -> ((i32, i32), (i32, i32)) {
+		let s = s - self.origin;
+		let cell = |x: f32| (x / self.cell).floor() as i32;
+		((cell(s.x - r), cell(s.y - r)), (cell(s.x + r), cell(s.y + r)))
+	}
+
+	pub fn insert(&mut self, i: usize, s: vec2<f32>, r: f32) {
+		self.all.push(i);
+		if self.too_big(r) {
+			self.big.push(i);
+			return;
+		}
+		let ((x0, y0), (x1, y1)) = self.span(s, r);
+		for x in x0..x1 + 1 {
+			for y in y0..y1 + 1 {
+				self.cells.entry((x, y)).or_insert_with(Vec::new).push(i);
+			}
+		}
+	}
+
+	/* Every body sharing a cell with a circle (or too big to say), in ascending order */
+	pub fn neighbors(&self, s: vec2<f32>, r: f32) -> Vec<usize> {
+		let mut found = if self.too_big(r) {
+			self.all.clone()
+		} else {
+			let ((x0, y0), (x1, y1)) = self.span(s, r);
+			let mut found = self.big.clone();
+			for x in x0..x1 + 1 {
+				for y in y0..y1 + 1 {
+					if let Some(bodies) = self.cells.get(&(x, y)) {
+						found.extend(bodies.iter());
+					}
+				}
+			}
+			found
+		};
+		found.sort();
+		found.dedup();
+		found
+	}
+}
+
+/* Every pair (i < j) of bodies which might touch, in the same order as a brute-force scan */
+pub fn candidate_pairs(state: &[Object]) -> Vec<(usize, usize)> {
+	let hash = SpatialHash::build(state);
+	let mut pairs = Vec::new();
+	for (i, o) in state.iter().enumerate() {
+		pairs.extend(hash.neighbors(o.s, o.r).into_iter().filter(|&j| j > i).map(|j| (i, j)));
+	}
+	pairs
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use random::Rng;
+
+	/* Every pair of massive bodies which actually overlap, the slow way */
+	fn brute_force(state: &[Object]) -> Vec<(usize, usize)> {
+		let mut pairs = Vec::new();
+		for i in 0..state.len() {
+			for j in i + 1..state.len() {
+				let (a, b) = (&state[i], &state[j]);
+				if (b.s - a.s).normsq() < (a.r + b.r) * (a.r + b.r) {
+					pairs.push((i, j));
+				}
+			}
+		}
+		pairs
+	}
+
+	fn scene(n: usize, seed: u64) -> Vec<Object> {
+		let mut rng = Rng::new(seed);
+		(0..n).map(|i| Object {
+			s: vec2::new(1000.0 * rng.next_f32(), 1000.0 * rng.next_f32()),
+			v: vec2::new(0.0, 0.0),
+			m: 1.0,
+			/* Mostly small, with the odd giant */
+			r: if i % 50 == 0 { 200.0 * rng.next_f32() } else { 30.0 * rng.next_f32() },
+			fixed: false,
+		}).collect()
+	}
+
+	#[test]
+	fn finds_every_overlap() {
+		for seed in 1..6 {
+			let state = scene(500, seed);
+			let candidates = candidate_pairs(state.as_slice());
+			let overlaps = brute_force(state.as_slice());
+			assert!(!overlaps.is_empty());
+			for pair in overlaps {
+				assert!(candidates.contains(&pair), "seed {} missed {:?}", seed, pair);
+			}
+
+			let mut sorted = candidates.clone();
+			sorted.sort();
+			sorted.dedup();
+			assert_eq!(candidates, sorted);
+		}
+	}
+
+	#[test]
+	fn degenerate_scenes() {
+		assert!(candidate_pairs(&[]).is_empty());
+
+		let mut state = scene(10, 7);
+		for o in state.iter_mut() {
+			o.s = vec2::new(5.0, 5.0);
+			o.r = 0.0;
+		}
+		assert_eq!(candidate_pairs(state.as_slice()).len(), 45);
+	}
+
+	#[test]
+	fn big_bodies_stay_out_of_the_grid() {
+		let mut hash = SpatialHash::new(1.0, vec2::new(0.0, 0.0));
+		hash.insert(0, vec2::new(0.5, 0.5), 0.1);
+		hash.insert(1, vec2::new(50.0, 50.0), 10.0);
+		hash.insert(2, vec2::new(90.5, 90.5), 0.1);
+		assert_eq!(hash.neighbors(vec2::new(0.5, 0.5), 0.1), vec![0, 1]);
+		assert_eq!(hash.neighbors(vec2::new(60.0, 60.0), 10.0), vec![0, 1, 2]);
+		assert_eq!(hash.cells.values().map(|c| c.len()).sum::<usize>(), 2);
+	}
+}
@@ -0,0 +1,130 @@
+use Object;
+
+/* How a body's mass changes over (simulated) time */
+#[derive(Clone, Debug, PartialEq)]
+pub enum MassSchedule {
+	/* Mass gained per second; negative to lose it */
+	Rate(f32),
+	/* (t, m) samples in order of t, interpolated linearly and held constant outside them */
+	Table(Vec<(f32, f32)>),
+}
+
+fn sample(samples: &[(f32, f32)], t: f32) -> f32 {
+	match samples.iter().position(|&(ts, _)| ts > t) {
+		None => samples.last().map_or(0.0, |&(_, m)| m),
+		Some(0) => samples[0].1,
+		Some(k) => {
+			let ((t0, m0), (t1, m1)) = (samples[k - 1], samples[k]);
+			m0 + (m1 - m0) * (t - t0) / (t1 - t0)
+		},
+	}
+}
+
+impl MassSchedule {
+	/* How much the mass changes from t0 to t1 */
+	pub fn change(&self, t0: f32, t1: f32) -> f32 {
+		match *self {
+			MassSchedule::Rate(rate) => rate * (t1 - t0),
+			MassSchedule::Table(ref samples) => sample(samples, t1) - sample(samples, t0),
+		}
+	}
+}
+
+/*
+ * The mass schedule (if any) of each body. Schedules only ever nudge the mass, so that e.g. a body
+ * which swallowed another keeps the extra mass.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct MassSchedules {
+	bodies: Vec<Option<MassSchedule>>,
+	/* Whether radii grow and shrink along with mass, at constant (surface) density */
+	pub scale_radius: bool,
+}
+
+impl MassSchedules {
+	pub fn new(bodies: Vec<Option<MassSchedule>>) -> MassSchedules {
+		MassSchedules {
+			bodies,
+			scale_radius: false,
+		}
+	}
+
+	/* Forget the schedules of bodies which were removed */
+	pub fn remove(&mut self, removed: &[usize]) {
+		for &i in removed.iter().rev() {
+			if i < self.bodies.len() {
+				self.bodies.remove(i);
+			}
+		}
+	}
+
+	/* Change the mass of every body with a schedule by however much it changed from t0 to t1 */
+	pub fn apply(&self, state: &mut [Object], t0: f32, t1: f32) {
+		for (o, schedule) in state.iter_mut().zip(self.bodies.iter()) {
+			if let Some(ref schedule) = *schedule {
+				let m = (o.m + schedule.change(t0, t1)).max(0.0);
+				if self.scale_radius && o.m > 0.0 {
+					o.r *= (m / o.m).sqrt();
+				}
+				o.m = m;
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use math::vec2;
+
+	fn body(m: f32, r: f32) -> Object {
+		Object {
+			s: vec2::new(0.0, 0.0),
+			v: vec2::new(0.0, 0.0),
+			m,
+			r,
+			fixed: false,
+		}
+	}
+
+	#[test]
+	fn rate() {
+		assert_eq!(MassSchedule::Rate(2.0).change(1.0, 4.0), 6.0);
+		assert_eq!(MassSchedule::Rate(-0.5).change(0.0, 2.0), -1.0);
+	}
+
+	#[test]
+	fn table() {
+		let table = MassSchedule::Table(vec![(1.0, 10.0), (3.0, 20.0), (4.0, 0.0)]);
+		/* Held before the first sample and after the last */
+		assert_eq!(table.change(-5.0, 1.0), 0.0);
+		assert_eq!(table.change(4.0, 100.0), 0.0);
+		assert_eq!(table.change(1.0, 2.0), 5.0);
+		assert_eq!(table.change(2.0, 3.5), -5.0);
+		assert_eq!(table.change(0.0, 100.0), -10.0);
+	}
+
+	#[test]
+	fn apply() {
+		let mut schedules = MassSchedules::new(vec![
+			Some(MassSchedule::Rate(3.0)),
+			None,
+			Some(MassSchedule::Rate(-10.0)),
+		]);
+		schedules.scale_radius = true;
+		let mut state = [body(1.0, 1.0), body(1.0, 1.0), body(1.0, 1.0)];
+		schedules.apply(&mut state, 0.0, 1.0);
+		/* Four times the mass takes up four times the area */
+		assert_eq!((state[0].m, state[0].r), (4.0, 2.0));
+		assert_eq!((state[1].m, state[1].r), (1.0, 1.0));
+		/* Mass never goes negative */
+		assert_eq!((state[2].m, state[2].r), (0.0, 0.0));
+
+		/* Only the last schedule is left, now for the first body */
+		schedules.remove(&[0, 1]);
+		let mut state = [body(20.0, 1.0)];
+		schedules.apply(&mut state, 0.0, 1.0);
+		assert_eq!(state[0].m, 10.0);
+	}
+}
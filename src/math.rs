@@ -2,6 +2,8 @@ use std::iter;
 
 use std::ops::{Add, Sub, Neg, Mul};
 
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
 pub trait Additive where
 Self: Sized {
 	const ZERO: Self;
@@ -89,7 +91,31 @@ macro_rules! module_impl {
 
 module_impl! { usize u8 u16 u32 u64 isize i8 i16 i32 i64 f32 f64 }
 
-/* TODO: Implement Field */
+/* Linear interpolation from a (at t = 0) to b (at t = 1) */
+pub fn lerp<T, K>(a: T, b: T, t: K) -> T where
+T: Additive + Mul<K, Output = T> + Copy {
+	Additive::add(a, Additive::sub(b, a) * t)
+}
+
+pub trait Field where
+Self: Ring {
+	fn recip(self) -> Self;
+	fn div(self, n: Self) -> Self {
+		self.mul(n.recip())
+	}
+}
+
+macro_rules! field_impl {
+	($($t:ty)*) => ($(
+		impl Field for $t {
+			fn recip(self) -> $t { 1.0 / self }
+			fn div(self, n: $t) -> $t { self / n }
+		}
+	)*)
+}
+
+field_impl! { f32 f64 }
+
 pub trait Algebraic where
 Self: Ring {
 	fn sqrt(self) -> Self;
@@ -117,6 +143,20 @@ impl<T> vec2<T> {
 	}
 }
 
+/* As an [x, y] pair */
+impl<T: Serialize> Serialize for vec2<T> {
+	fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+		(&self.x, &self.y).serialize(s)
+	}
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for vec2<T> {
+	fn deserialize<D: Deserializer<'de>>(d: D) -> Result<vec2<T>, D::Error> {
+		let (x, y) = <(T, T)>::deserialize(d)?;
+		Ok(vec2 { x, y })
+	}
+}
+
 impl<T: Additive> Add for vec2<T> {
 	type Output = vec2<T>;
 
@@ -177,12 +217,314 @@ K: Ring + Copy {
 	}
 }
 
+impl<T, K> Module<K> for vec2<T> where
+T: Module<K> + Copy,
+K: Ring + Copy {
+	fn scale(self, n: K) -> vec2<T> {
+		self * n
+	}
+}
+
+impl<T> vec2<T> where
+T: Ring + Copy, {
+	pub fn normsq(self) -> T {
+		self.dot(self)
+	}
+	pub fn norm<A: Algebraic + From<T>>(self) -> A {
+		A::from(self.normsq()).sqrt()
+	}
+	pub fn distance<A: Algebraic + From<T>>(self, v: vec2<T>) -> A {
+		(self - v).norm()
+	}
+	pub fn dot(self, v: vec2<T>) -> T {
+		self.x.mul(v.x).add(self.y.mul(v.y))
+	}
+	/* The z component of the cross product, as if both were in the xy plane */
+	pub fn cross(self, v: vec2<T>) -> T {
+		self.x.mul(v.y).sub(self.y.mul(v.x))
+	}
+}
+
+impl<T> vec2<T> where
+T: Field + Algebraic + Module<T> + PartialEq + Copy, {
+	/* The unit vector pointing the same way; the zero vector stays zero */
+	pub fn normalize(self) -> vec2<T> {
+		let nsq = self.normsq();
+		if nsq == T::ZERO {
+			return self;
+		}
+		self * nsq.sqrt().recip()
+	}
+}
+
 impl<T> vec2<T> where
+T: PartialOrd + Copy, {
+	/* Componentwise minimum */
+	pub fn min(self, v: vec2<T>) -> vec2<T> {
+		vec2 {
+			x: if v.x < self.x { v.x } else { self.x },
+			y: if v.y < self.y { v.y } else { self.y },
+		}
+	}
+	/* Componentwise maximum */
+	pub fn max(self, v: vec2<T>) -> vec2<T> {
+		vec2 {
+			x: if v.x > self.x { v.x } else { self.x },
+			y: if v.y > self.y { v.y } else { self.y },
+		}
+	}
+}
+
+/* Like vec2, but for leaving the plane */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct vec3<T>{
+	pub x: T,
+	pub y: T,
+	pub z: T,
+}
+
+impl<T> vec3<T> {
+	pub fn new(x: T, y: T, z: T) -> vec3<T> {
+		vec3 {x, y, z}
+	}
+}
+
+impl<T: Additive> Add for vec3<T> {
+	type Output = vec3<T>;
+
+	fn add(self, v: vec3<T>) -> vec3<T> {
+		vec3 {
+			x: self.x.add(v.x),
+			y: self.y.add(v.y),
+			z: self.z.add(v.z),
+		}
+	}
+}
+
+impl<T: Additive> Sub for vec3<T> {
+	type Output = vec3<T>;
+
+	fn sub(self, v: vec3<T>) -> vec3<T> {
+		vec3 {
+			x: self.x.sub(v.x),
+			y: self.y.sub(v.y),
+			z: self.z.sub(v.z),
+		}
+	}
+}
+
+impl<T: Additive> Neg for vec3<T> {
+	type Output = vec3<T>;
+
+	fn neg(self) -> vec3<T> {
+		vec3 {
+			x: self.x.neg(),
+			y: self.y.neg(),
+			z: self.z.neg(),
+		}
+	}
+}
+
+impl<T: Additive + Copy> Additive for vec3<T> {
+	const ZERO: vec3<T> = vec3 {
+		x: T::ZERO,
+		y: T::ZERO,
+		z: T::ZERO,
+	};
+	fn add(self, v: Self) -> Self {
+		self + v
+	}
+	fn sub(self, v: Self) -> Self {
+		self - v
+	}
+	fn neg(self) -> Self { -self }
+}
+
+impl<T, K> Mul<K> for vec3<T> where
+T: Module<K>,
+K: Ring + Copy {
+	type Output = vec3<T>;
+
+	fn mul(self, n: K) -> vec3<T> {
+		vec3 {
+			x: self.x.scale(n),
+			y: self.y.scale(n),
+			z: self.z.scale(n),
+		}
+	}
+}
+
+impl<T, K> Module<K> for vec3<T> where
+T: Module<K> + Copy,
+K: Ring + Copy {
+	fn scale(self, n: K) -> vec3<T> {
+		self * n
+	}
+}
+
+impl<T> vec3<T> where
 T: Ring + Copy, {
 	pub fn normsq(self) -> T {
-		self.x.pow(2).add(self.y.pow(2))
+		self.dot(self)
 	}
 	pub fn norm<A: Algebraic + From<T>>(self) -> A {
 		A::from(self.normsq()).sqrt()
 	}
+	pub fn distance<A: Algebraic + From<T>>(self, v: vec3<T>) -> A {
+		(self - v).norm()
+	}
+	pub fn dot(self, v: vec3<T>) -> T {
+		self.x.mul(v.x).add(self.y.mul(v.y)).add(self.z.mul(v.z))
+	}
+	pub fn cross(self, v: vec3<T>) -> vec3<T> {
+		vec3 {
+			x: self.y.mul(v.z).sub(self.z.mul(v.y)),
+			y: self.z.mul(v.x).sub(self.x.mul(v.z)),
+			z: self.x.mul(v.y).sub(self.y.mul(v.x)),
+		}
+	}
+}
+
+impl<T> vec3<T> where
+T: Field + Algebraic + Module<T> + PartialEq + Copy, {
+	/* The unit vector pointing the same way; the zero vector stays zero */
+	pub fn normalize(self) -> vec3<T> {
+		let nsq = self.normsq();
+		if nsq == T::ZERO {
+			return self;
+		}
+		self * nsq.sqrt().recip()
+	}
+}
+
+/* A 2x2 matrix, stored as its rows */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct mat2<T>{
+	pub x: vec2<T>,
+	pub y: vec2<T>,
+}
+
+impl<T> mat2<T> {
+	pub fn new(x: vec2<T>, y: vec2<T>) -> mat2<T> {
+		mat2 {x, y}
+	}
+}
+
+impl<T> mat2<T> where
+T: Ring + Copy, {
+	pub fn identity() -> mat2<T> {
+		mat2 {
+			x: vec2::new(T::ONE, T::ZERO),
+			y: vec2::new(T::ZERO, T::ONE),
+		}
+	}
+	pub fn transpose(self) -> mat2<T> {
+		mat2 {
+			x: vec2::new(self.x.x, self.y.x),
+			y: vec2::new(self.x.y, self.y.y),
+		}
+	}
+}
+
+macro_rules! mat2_impl_float {
+	($($t:ty)*) => ($(
+		impl mat2<$t> {
+			/* Rotates vectors counterclockwise by angle (in radians) */
+			pub fn rotation(angle: $t) -> mat2<$t> {
+				let (sin, cos) = angle.sin_cos();
+				mat2 {
+					x: vec2::new(cos, -sin),
+					y: vec2::new(sin, cos),
+				}
+			}
+		}
+	)*)
+}
+
+mat2_impl_float! { f32 f64 }
+
+impl<T> Mul<vec2<T>> for mat2<T> where
+T: Ring + Copy {
+	type Output = vec2<T>;
+
+	fn mul(self, v: vec2<T>) -> vec2<T> {
+		vec2 {
+			x: self.x.dot(v),
+			y: self.y.dot(v),
+		}
+	}
+}
+
+impl<T> Mul<mat2<T>> for mat2<T> where
+T: Ring + Copy {
+	type Output = mat2<T>;
+
+	fn mul(self, m: mat2<T>) -> mat2<T> {
+		let cols = m.transpose();
+		mat2 {
+			x: vec2::new(self.x.dot(cols.x), self.x.dot(cols.y)),
+			y: vec2::new(self.y.dot(cols.x), self.y.dot(cols.y)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::f32::consts::PI;
+
+	fn close(a: vec2<f32>, b: vec2<f32>) -> bool {
+		(a - b).normsq() < 1.0e-12
+	}
+
+	#[test]
+	fn normalize() {
+		assert!(close(vec2::new(3.0f32, 4.0).normalize(), vec2::new(0.6, 0.8)));
+		assert!(close(vec2::new(0.0f32, 0.0).normalize(), vec2::ZERO));
+		let v = vec3::new(2.0f32, 0.0, 0.0).normalize();
+		assert_eq!((v.x, v.y, v.z), (1.0, 0.0, 0.0));
+	}
+
+	#[test]
+	fn dot_and_cross() {
+		let (x, y) = (vec2::new(2.0f32, 0.0), vec2::new(0.0f32, 3.0));
+		assert_eq!(x.dot(y), 0.0);
+		assert_eq!(x.cross(y), 6.0);
+		assert_eq!(y.cross(x), -6.0);
+		assert_eq!(x.dot(x * 2.0f32), 8.0);
+		assert_eq!(x.cross(x * 2.0f32), 0.0);
+	}
+
+	#[test]
+	fn vec3_cross() {
+		let z = vec3::new(1.0f32, 0.0, 0.0).cross(vec3::new(0.0, 1.0, 0.0));
+		assert_eq!((z.x, z.y, z.z), (0.0, 0.0, 1.0));
+		let (a, b) = (vec3::new(1.0f32, 2.0, 3.0), vec3::new(-4.0f32, 0.5, 2.0));
+		let c = a.cross(b);
+		assert_eq!(c.dot(a), 0.0);
+		assert_eq!(c.dot(b), 0.0);
+	}
+
+	/* Only needs what Module promises, so works on scalars and vectors alike */
+	fn midpoint<T: Module<f32> + Copy>(a: T, b: T) -> T {
+		a.add(b).scale(0.5)
+	}
+
+	#[test]
+	fn module_generic() {
+		assert_eq!(midpoint(1.0f32, 3.0), 2.0);
+		assert!(close(midpoint(vec2::new(1.0, 0.0), vec2::new(3.0, 2.0)), vec2::new(2.0, 1.0)));
+		let quarter = lerp(vec2::new(0.0, 0.0), vec2::new(4.0, 8.0), 0.25f32);
+		assert!(close(quarter, vec2::new(1.0, 2.0)));
+	}
+
+	#[test]
+	fn rotation() {
+		let r = mat2::rotation(PI / 2.0);
+		assert!(close(r * vec2::new(1.0, 0.0), vec2::new(0.0, 1.0)));
+		assert!(close((r * r.transpose()) * vec2::new(3.0, -2.0), vec2::new(3.0, -2.0)));
+		let id = mat2::<f32>::identity() * vec2::new(5.0, 7.0);
+		assert_eq!((id.x, id.y), (5.0, 7.0));
+	}
 }
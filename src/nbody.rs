@@ -1,31 +1,90 @@
 #![feature(associated_consts)]
+#![cfg_attr(test, feature(test))]
 #![allow(non_camel_case_types)]
 
 use math::{vec2, Additive};
 mod math;
+use camera::{approach, fit, framed_box, nice_length};
+mod camera;
+use frame::Frame;
+mod frame;
+use diag::{EnergyLog, TrajectoryLog, angular_momentum, angular_momentum_about, beyond,
+	center_of_mass, escape_velocity, escaping, is_bound, kinetic_energy, potential_energy,
+	recenter_momentum, recenter_position, scale_to_virial, speed_histogram, total_momentum,
+	velocity_dispersion, virial_ratio};
+mod diag;
+use orbit::{Apsides, Closure, OrbitalElements, circular_binary, find_resonances, orbital_elements,
+	primary_of};
+mod orbit;
+use plot::{Plot, draw_bars, draw_grid};
+mod plot;
+use profile::{Profile, time_forces};
+mod profile;
+use scene::{Settings, parse_scene, parse_scene_json};
+mod scene;
+use quadtree::BarnesHut;
+mod quadtree;
+mod simd;
+use mass::MassSchedules;
+mod mass;
+use force::{Background, Drag, External, Force, PostNewtonian, apply};
+mod force;
+use collide::{Collisions, collide, merge};
+mod collide;
+mod hash;
+use trail::Trails;
+mod trail;
+use random::plummer;
+mod random;
+use record::Recorder;
+mod record;
+#[cfg(feature = "gpu")]
+mod gpu;
+#[cfg(test)]
+mod testing;
 
 use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs::File;
 use std::io;
+use std::io::BufReader;
+use std::process;
 use std::ops::Deref;
 use std::rc::Rc;
+use std::str::FromStr;
 
 extern crate rayon;
 
 use rayon::prelude::*;
 
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
 extern crate sfml;
 
+#[cfg(feature = "gpu")]
+extern crate pollster;
+#[cfg(feature = "gpu")]
+extern crate wgpu;
+
 use sfml::system::{Clock, Time, Vector2f, Vector2i};
-use sfml::window::{ContextSettings, Event, Key, style, VideoMode,};
-use sfml::graphics::{Color, Drawable, Font, Image, RcSprite, RenderWindow, RenderTarget, Sprite, Text,
-	Texture, TextureRef, Transformable, View};
+use sfml::window::{ContextSettings, Event, Key, mouse, style, VideoMode,};
+use sfml::graphics::{CircleShape, Color, Drawable, Font, Image, PrimitiveType, RcSprite, RectangleShape,
+	RenderWindow, RenderTarget, Shape, Sprite, Text, Texture, TextureRef, Transformable, Vertex,
+	VertexArray, View};
 
 #[derive(Clone, Copy, Debug)]
 struct Object {
 	s: vec2<f32>,
 	v: vec2<f32>,
+	/* Massless bodies are tracers: they feel gravity but don't pull on anything, or collide */
 	m: f32,
+	/* Bodies closer than the sum of their radii collide */
+	r: f32,
+	/* Frozen in place, but still pulling on everything else */
+	fixed: bool,
 }
 
 #[derive(Clone, Copy, Debug, Default)]
@@ -37,58 +96,364 @@ struct Deriv {
 fn partial(o: &Object, d: &Deriv, dt: f32) -> Object {
 	Object {
 			m: o.m,
+			r: o.r,
 			s: o.s + d.ds * dt,
 			v: o.v + d.dv * dt,
+			fixed: o.fixed,
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Integrator {
+	Rk4,
+	Verlet,
+	Leapfrog,
+	/* Dormand-Prince 5(4); only really useful with --adaptive */
+	Dopri,
+	/* Fourth-order symplectic, built out of three leapfrog steps */
+	Yoshida,
+}
+
+impl FromStr for Integrator {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<Integrator, ()> {
+		match s {
+			"rk4" => Ok(Integrator::Rk4),
+			"verlet" => Ok(Integrator::Verlet),
+			"leapfrog" => Ok(Integrator::Leapfrog),
+			"dopri" => Ok(Integrator::Dopri),
+			"yoshida" => Ok(Integrator::Yoshida),
+			_ => Err(()),
+		}
+	}
+}
+
+/* Knobs which change the physics being simulated */
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct SimConfig {
+	/* Whether bodies attract each other at all */
+	gravity: bool,
+	/* The gravitational constant, in whatever units the scene uses */
+	g: f32,
+	/* Whether to calculate forces on the GPU */
+	gpu: bool,
+	/* Scenes with fewer bodies than this calculate forces serially */
+	serial_below: usize,
+	/* Softening length, so close passes don't fling bodies off to infinity; 0 for point masses */
+	eps: f32,
+	/* Scenes with more bodies than this use Barnes-Hut instead of summing every pair */
+	bh_above: usize,
+	/* If present, use (or don't use) Barnes-Hut whatever the body count */
+	force_bh: Option<bool>,
+	/* Barnes-Hut opening angle; smaller is more accurate but slower */
+	theta: f32,
+	/* Whether to sum every pair with the vectorized kernel, at any body count */
+	simd: bool,
+	collisions: Collisions,
+	/* How much of their approach speed colliding bodies bounce back with */
+	restitution: f32,
+	integrator: Integrator,
+	/* If present, adapt the step size to keep the relative error in each step under this */
+	tol: Option<f32>,
+	/* Bounds on adaptive steps; by default steps may shrink to a millionth of a tick */
+	min_dt: Option<f32>,
+	max_dt: Option<f32>,
+	/* If present, space wraps around with this period in both directions */
+	period: Option<f32>,
+	/* If present, correct gravity to first post-Newtonian order with this speed of light */
+	c: Option<f32>,
+	drag: Option<Drag>,
+	background: Option<Background>,
+	/* If present, warn about unbound bodies leaving past this distance from the barycenter */
+	escape: Option<f32>,
+	/* Whether to remove such bodies, rather than just warning */
+	remove_escaped: bool,
+}
+
+/* The ways diff can sum up gravity */
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Summation {
+	Off,
+	Serial,
+	Parallel,
+	Simd,
+	BarnesHut,
+}
+
+impl SimConfig {
+	/* How gravity gets summed for n bodies */
+	fn summation(&self, n: usize) -> Summation {
+		/* Asking for the vectorized kernel is asking to sum every pair, however many there are */
+		let bh = self.force_bh.unwrap_or(!self.simd && n > self.bh_above);
+		if !self.gravity {
+			Summation::Off
+		} else if bh {
+			Summation::BarnesHut
+		} else if self.simd {
+			Summation::Simd
+		} else if n < self.serial_below {
+			Summation::Serial
+		} else {
+			Summation::Parallel
+		}
+	}
+
+	/* Keep up with the (ascending) bodies removed from the simulation */
+	fn remove(&mut self, removed: &[usize]) {
+		if let Some(drag) = self.drag {
+			self.drag = drag.remove(removed);
+			if self.drag.is_none() {
+				eprintln!("the body drag was centered on is gone; turning drag off");
+			}
+		}
+	}
+
+	/*
+	 * Everything acting on the bodies besides plain gravity. Turning gravity off turns off all of
+	 * it that is gravitational, so bodies coast.
+	 */
+	fn forces(&self) -> Vec<Box<Force>> {
+		let mut forces: Vec<Box<Force>> = Vec::new();
+		if let (Some(c), true) = (self.c, self.gravity) {
+			forces.push(Box::new(PostNewtonian {
+				g: self.g,
+				c,
+				period: self.period,
+			}));
+		}
+		if let Some(drag) = self.drag {
+			forces.push(Box::new(drag));
+		}
+		if let (Some(potential), true) = (self.background, self.gravity) {
+			forces.push(Box::new(External {
+				potential,
+				g: self.g,
+			}));
+		}
+		forces
 	}
 }
 
+impl Default for SimConfig {
+	fn default() -> SimConfig {
+		SimConfig {
+			gravity: true,
+			g: G,
+			gpu: false,
+			serial_below: 32,
+			eps: 0.0,
+			bh_above: 1024,
+			force_bh: None,
+			theta: 0.5,
+			simd: false,
+			collisions: Collisions::Elastic,
+			restitution: 1.0,
+			integrator: Integrator::Rk4,
+			tol: None,
+			min_dt: None,
+			max_dt: None,
+			period: None,
+			c: None,
+			drag: None,
+			background: None,
+			escape: None,
+			remove_escaped: false,
+		}
+	}
+}
+
+/* The shortest way from one point to another d away, when space wraps around every period */
+fn min_image(d: vec2<f32>, period: Option<f32>) -> vec2<f32> {
+	match period {
+		Some(l) => vec2::new(d.x - l * (d.x / l).round(), d.y - l * (d.y / l).round()),
+		None => d,
+	}
+}
+
+/* Bring a position which has left the box back in through the other side */
+fn wrap(p: vec2<f32>, l: f32) -> vec2<f32> {
+	let h = 0.5 * l;
+	vec2::new((p.x + h).rem_euclid(l) - h, (p.y + h).rem_euclid(l) - h)
+}
+
 const G: f32 = 6.67408e-11;
-/* The gravitational acceleration that b exerts on a */
-fn grav(a: &Object, b: &Object) -> vec2<f32> {
-	let ba = b.s - a.s;
-	let rsq = ba.normsq();
-	let mag = G * b.m / rsq;
+/* The gravitational acceleration that b exerts on a, Plummer-softened by eps */
+fn grav(a: &Object, b: &Object, cfg: &SimConfig) -> vec2<f32> {
+	if b.m == 0.0 {
+		return vec2::ZERO;
+	}
+	let ba = min_image(b.s - a.s, cfg.period);
+	let rsq = ba.normsq() + cfg.eps * cfg.eps;
+	let mag = cfg.g * b.m / rsq;
 	ba * mag * (1.0 / rsq.sqrt())
 }
 
-fn diff(init: &[Object], t: f32, dt: f32, derivs: &[Deriv]) -> Vec<Deriv> {
-	/* First calculate a new state based on the derivatives */
-	let new = init.par_iter()
+/* A new state based on the derivatives */
+fn partials(init: &[Object], derivs: &[Deriv], dt: f32) -> Vec<Object> {
+	init.par_iter()
 		.zip(derivs.par_iter())
 		.map(|(o, d)| partial(o, d, dt))
-		.collect::<Vec<Object>>();
-	/* Now calculate the new acceleration */
-	/* TODO: cache results */
-	new.par_iter()
-		.enumerate()
-		.map(|(i, a)| -> vec2<f32> {
-			new.par_iter()
-				.take(i)
-				.map(|b: &Object| grav(a, b))
-				.reduce(|| vec2::ZERO, |a, v| a + v)
-			+ new.par_iter()
-				.skip(i + 1)
-				.map(|b: &Object| grav(a, b))
-				.reduce(|| vec2::ZERO, |a, v| a + v)
-		})
-	/* And zip it with the velocity for the new derivatives */
+		.collect()
+}
+
+/* Zip the accelerations with the velocity for the new derivatives */
+fn to_derivs(new: &[Object], acc: &[vec2<f32>]) -> Vec<Deriv> {
+	acc.par_iter()
 		.zip(new.par_iter())
-		.map(|(a, o)| Deriv {
-			ds: o.v,
-			dv: a,
+		.map(|(&a, o)| if o.fixed {
+			Deriv::default()
+		} else {
+			Deriv {
+				ds: o.v,
+				dv: a,
+			}
 		})
 		.collect()
 }
 
+/* Just the positions and masses of the bodies, packed so the force loop doesn't drag velocities along */
+struct Bodies {
+	s: Vec<vec2<f32>>,
+	m: Vec<f32>,
+	/* The (ascending) indices of everything but tracers */
+	massive: Vec<usize>,
+}
+
+impl Bodies {
+	fn new(state: &[Object]) -> Bodies {
+		Bodies {
+			s: state.iter().map(|o| o.s).collect(),
+			m: state.iter().map(|o| o.m).collect(),
+			massive: (0..state.len()).filter(|&i| state[i].m != 0.0).collect(),
+		}
+	}
+
+	/*
+	 * Accumulate the pull between body i and every body after it. Two tracers can't pull on each
+	 * other, so tracers only pair up with massive bodies.
+	 */
+	fn pull_later(&self, acc: &mut [vec2<f32>], i: usize, cfg: &SimConfig) {
+		if self.m[i] != 0.0 {
+			for j in i + 1..self.s.len() {
+				self.pull(acc, i, j, cfg);
+			}
+		} else {
+			let start = self.massive.binary_search(&(i + 1)).unwrap_or_else(|k| k);
+			for &j in &self.massive[start..] {
+				self.pull(acc, i, j, cfg);
+			}
+		}
+	}
+
+	/* Accumulate the pull that bodies i and j exert on each other */
+	fn pull(&self, acc: &mut [vec2<f32>], i: usize, j: usize, cfg: &SimConfig) {
+		let ba = min_image(self.s[j] - self.s[i], cfg.period);
+		let rsq = ba.normsq() + cfg.eps * cfg.eps;
+		let f = ba * (cfg.g / (rsq * rsq.sqrt()));
+		acc[i] = acc[i] + f * self.m[j];
+		acc[j] = acc[j] - f * self.m[i];
+	}
+}
+
+/* The gravitational acceleration of every body due to every other body, one pair at a time */
+fn accelerations(state: &[Object], cfg: &SimConfig) -> Vec<vec2<f32>> {
+	let n = state.len();
+	let bodies = Bodies::new(state);
+	(0..n).into_par_iter()
+		.fold(|| vec![vec2::ZERO; n], |mut acc, i| {
+			bodies.pull_later(acc.as_mut_slice(), i, cfg);
+			acc
+		})
+		.reduce(|| vec![vec2::ZERO; n], |mut acc, other| {
+			for (a, &b) in acc.iter_mut().zip(other.iter()) {
+				*a = *a + b;
+			}
+			acc
+		})
+}
+
+/* Like accelerations, but without rayon's overhead for tiny scenes */
+fn accelerations_serial(state: &[Object], cfg: &SimConfig) -> Vec<vec2<f32>> {
+	let n = state.len();
+	let bodies = Bodies::new(state);
+	let mut acc = vec![vec2::ZERO; n];
+	for i in 0..n {
+		bodies.pull_later(acc.as_mut_slice(), i, cfg);
+	}
+	acc
+}
+
+/* The accelerations calculated last, along with the bodies and settings they were for */
+thread_local!(static LAST_ACC: RefCell<Option<(Vec<Object>, SimConfig, Vec<vec2<f32>>)>> =
+	RefCell::new(None));
+
+/* How many times the cache has missed, for testing */
+#[cfg(test)]
+thread_local!(static MISSES: ::std::cell::Cell<usize> = ::std::cell::Cell::new(0));
+
+/*
+ * Calculate accelerations with f, unless they were just calculated for bodies with the same
+ * positions and masses. This makes e.g. the first stage of a Verlet step (where the last one left
+ * off) or of an adaptive step (which starts twice from the same state) free.
+ */
+fn cached_accelerations<F>(state: &[Object], cfg: &SimConfig, f: F) -> Vec<vec2<f32>> where
+F: FnOnce() -> Vec<vec2<f32>> {
+	let hit = LAST_ACC.with(|last| match *last.borrow() {
+		Some((ref s, ref c, ref acc)) if c == cfg && s.len() == state.len() && s.iter()
+			.zip(state.iter())
+			.all(|(a, b)| a.s.x == b.s.x && a.s.y == b.s.y && a.m == b.m) => Some(acc.clone()),
+		_ => None,
+	});
+	hit.unwrap_or_else(|| {
+		#[cfg(test)]
+		MISSES.with(|n| n.set(n.get() + 1));
+		let acc = f();
+		LAST_ACC.with(|last| *last.borrow_mut() = Some((state.to_vec(), *cfg, acc.clone())));
+		acc
+	})
+}
+
+fn diff(init: &[Object], t: f32, dt: f32, derivs: &[Deriv], cfg: &SimConfig) -> Vec<Deriv> {
+	/* First calculate a new state based on the derivatives */
+	let new = partials(init, derivs, dt);
+	/* Now calculate the new acceleration */
+	let n = new.len();
+	let acc = cached_accelerations(new.as_slice(), cfg, || time_forces(|| match cfg.summation(n) {
+		Summation::Off => vec![vec2::ZERO; n],
+		Summation::Serial => accelerations_serial(new.as_slice(), cfg),
+		Summation::Parallel => accelerations(new.as_slice(), cfg),
+		Summation::Simd => simd::accelerations(new.as_slice(), cfg),
+		Summation::BarnesHut => BarnesHut::new(new.as_slice()).accelerations(cfg),
+	}));
+	/* These may depend on velocities, so they are never cached */
+	let acc = apply(cfg.forces().as_slice(), new.as_slice(), acc);
+	to_derivs(new.as_slice(), acc.as_slice())
+}
+
+type DiffFn = fn(&[Object], f32, f32, &[Deriv], &SimConfig) -> Vec<Deriv>;
+
+#[cfg(feature = "gpu")]
+fn diff_fn(cfg: &SimConfig) -> DiffFn {
+	if cfg.gpu { gpu::diff_gpu } else { diff }
+}
+
+#[cfg(not(feature = "gpu"))]
+fn diff_fn(_: &SimConfig) -> DiffFn {
+	diff
+}
+
 fn weight(a: vec2<f32>, b: vec2<f32>, c: vec2<f32>, d: vec2<f32>) -> vec2<f32> {
 	(a + (b + c)*2.0 + d) * (1.0/6.0)
 }
 
-fn integrate(state: &[Object], t: f32, dt: f32) -> Vec<Object> {
-	let a = diff(state, t, 0.0, vec![Deriv::default(); state.len()].as_slice());
-	let b = diff(state, t, 0.5 * dt, a.as_slice());
-	let c = diff(state, t, 0.5 * dt, b.as_slice());
-	let d = diff(state, t, dt, c.as_slice());
+fn integrate(state: &[Object], t: f32, dt: f32, cfg: &SimConfig) -> Vec<Object> {
+	let diff = diff_fn(cfg);
+	let a = diff(state, t, 0.0, vec![Deriv::default(); state.len()].as_slice(), cfg);
+	let b = diff(state, t, 0.5 * dt, a.as_slice(), cfg);
+	let c = diff(state, t, 0.5 * dt, b.as_slice(), cfg);
+	let d = diff(state, t, dt, c.as_slice(), cfg);
 
 	a.par_iter().zip(b.par_iter().zip(c.par_iter().zip(d.par_iter())))
 		.map(|(a, (b, (c, d)))| Deriv {
@@ -100,73 +465,868 @@ fn integrate(state: &[Object], t: f32, dt: f32) -> Vec<Object> {
 		.collect()
 }
 
-fn preload_tex(cache: &mut HashMap<String, Rc<Texture>>, path: &str) {
-	cache.entry(path.into()).or_insert({
-		let img = Image::from_file(&path).expect(&format!("cannot load texture from {}", path));
-		img.create_mask_from_color(&Color::black(), 0);
-		Rc::new(Texture::from_image(&img).expect("could not convert image to texture"))
-	});
+/* Kick-drift-kick velocity Verlet; symplectic, so orbits stay closed over long runs */
+fn integrate_verlet(state: &[Object], t: f32, dt: f32, cfg: &SimConfig) -> Vec<Object> {
+	let diff = diff_fn(cfg);
+	let zero = vec![Deriv::default(); state.len()];
+
+	/* Kick by half a step, then drift a whole one at the new velocity */
+	let a = diff(state, t, 0.0, zero.as_slice(), cfg);
+	let drifted = state.par_iter()
+		.zip(a.par_iter())
+		.map(|(o, d)| partial(o, &Deriv {
+			ds: d.ds + d.dv * (0.5 * dt),
+			dv: d.dv * 0.5,
+		}, dt))
+		.collect::<Vec<Object>>();
+
+	/* And kick the rest of the way with the acceleration at the new positions */
+	let b = diff(drifted.as_slice(), t + dt, 0.0, zero.as_slice(), cfg);
+	drifted.par_iter()
+		.zip(b.par_iter())
+		.map(|(o, d)| partial(o, &Deriv {
+			ds: vec2::ZERO,
+			dv: d.dv * 0.5,
+		}, dt))
+		.collect()
+}
+
+/* Change every velocity by dt's worth of the acceleration at the current positions */
+fn kick(state: &[Object], t: f32, dt: f32, cfg: &SimConfig) -> Vec<Object> {
+	let a = diff_fn(cfg)(state, t, 0.0, vec![Deriv::default(); state.len()].as_slice(), cfg);
+	state.par_iter()
+		.zip(a.par_iter())
+		.map(|(o, d)| partial(o, &Deriv {
+			ds: vec2::ZERO,
+			dv: d.dv,
+		}, dt))
+		.collect()
+}
+
+/* Move every (free) body dt's worth along its current velocity */
+fn drift(state: &[Object], dt: f32) -> Vec<Object> {
+	state.par_iter()
+		.map(|o| if o.fixed { *o } else { partial(o, &Deriv { ds: o.v, dv: vec2::ZERO }, dt) })
+		.collect()
+}
+
+/* Move velocities half a step ahead of the positions */
+fn leapfrog_init(state: &[Object], t: f32, dt: f32, cfg: &SimConfig) -> Vec<Object> {
+	kick(state, t, 0.5 * dt, cfg)
+}
+
+/* Drift a whole step, then kick the (half-step) velocities a whole step */
+fn leapfrog_step(state: &[Object], t: f32, dt: f32, cfg: &SimConfig) -> Vec<Object> {
+	kick(drift(state, dt).as_slice(), t + dt, dt, cfg)
+}
+
+/* Bring velocities back in line with the positions */
+fn leapfrog_sync(state: &[Object], t: f32, dt: f32, cfg: &SimConfig) -> Vec<Object> {
+	kick(state, t, -0.5 * dt, cfg)
+}
+
+/*
+ * One self-contained leapfrog step, starting and ending with synchronized velocities. This is
+ * only used where the step size changes from step to step (adaptive steps), which isn't
+ * symplectic anyway; fixed steps keep the velocities staggered across ticks instead.
+ */
+fn integrate_leapfrog(state: &[Object], t: f32, dt: f32, cfg: &SimConfig) -> Vec<Object> {
+	let half = leapfrog_init(state, t, dt, cfg);
+	let half = leapfrog_step(half.as_slice(), t, dt, cfg);
+	leapfrog_sync(half.as_slice(), t + dt, dt, cfg)
+}
+
+/*
+ * Yoshida's fourth-order scheme: drift-kick leapfrog substeps of w1, w0, and w1 times dt, where the
+ * negative middle one cancels out the leading error of the other two
+ */
+fn integrate_yoshida(state: &[Object], t: f32, dt: f32, cfg: &SimConfig) -> Vec<Object> {
+	let cbrt2 = 2f32.powf(1.0 / 3.0);
+	let w1 = 1.0 / (2.0 - cbrt2);
+	let w0 = -cbrt2 / (2.0 - cbrt2);
+	let c = [0.5 * w1, 0.5 * (w0 + w1), 0.5 * (w0 + w1), 0.5 * w1];
+	let d = [w1, w0, w1];
+
+	let mut state = drift(state, c[0] * dt);
+	let mut now = t + c[0] * dt;
+	for i in 0..3 {
+		state = kick(state.as_slice(), now, d[i] * dt, cfg);
+		state = drift(state.as_slice(), c[i + 1] * dt);
+		now += c[i + 1] * dt;
+	}
+	state
+}
+
+/* Sum the stages ks, weighted by w */
+fn weighted(ks: &[Vec<Deriv>], w: &[f32]) -> Vec<Deriv> {
+	(0..ks[0].len()).into_par_iter()
+		.map(|i| ks.iter().zip(w.iter()).fold(Deriv::default(), |acc, (k, &w)| Deriv {
+			ds: acc.ds + k[i].ds * w,
+			dv: acc.dv + k[i].dv * w,
+		}))
+		.collect()
+}
+
+/*
+ * One Dormand-Prince step, returning both the fifth-order result and the embedded fourth-order
+ * one, whose difference estimates the error. The last stage is at the fifth-order result, so the
+ * acceleration cache makes it the first stage of the next step for free.
+ */
+fn integrate_dopri(state: &[Object], t: f32, dt: f32, cfg: &SimConfig) -> (Vec<Object>, Vec<Object>) {
+	const C: [f32; 6] = [1.0 / 5.0, 3.0 / 10.0, 4.0 / 5.0, 8.0 / 9.0, 1.0, 1.0];
+	const A: [&'static [f32]; 6] = [
+		&[1.0 / 5.0],
+		&[3.0 / 40.0, 9.0 / 40.0],
+		&[44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0],
+		&[19372.0 / 6561.0, -25360.0 / 2187.0, 64448.0 / 6561.0, -212.0 / 729.0],
+		&[9017.0 / 3168.0, -355.0 / 33.0, 46732.0 / 5247.0, 49.0 / 176.0, -5103.0 / 18656.0],
+		&[35.0 / 384.0, 0.0, 500.0 / 1113.0, 125.0 / 192.0, -2187.0 / 6784.0, 11.0 / 84.0],
+	];
+	const B4: [f32; 7] = [5179.0 / 57600.0, 0.0, 7571.0 / 16695.0, 393.0 / 640.0,
+		-92097.0 / 339200.0, 187.0 / 2100.0, 1.0 / 40.0];
+	let diff = diff_fn(cfg);
+	let zero = vec![Deriv::default(); state.len()];
+
+	let mut ks = vec![diff(state, t, 0.0, zero.as_slice(), cfg)];
+	for (&c, a) in C.iter().zip(A.iter()) {
+		let d = weighted(ks.as_slice(), a);
+		ks.push(diff(state, t + c * dt, dt, d.as_slice(), cfg));
+	}
+	/* The fifth-order weights are just the last row of A */
+	let high = partials(state, weighted(ks.as_slice(), A[5]).as_slice(), dt);
+	let low = partials(state, weighted(ks.as_slice(), &B4).as_slice(), dt);
+	(high, low)
+}
+
+/* Everything the rest of the simulation needs to know about each scheme */
+impl Integrator {
+	fn step(self, state: &[Object], t: f32, dt: f32, cfg: &SimConfig) -> Vec<Object> {
+		match self {
+			Integrator::Rk4 => integrate(state, t, dt, cfg),
+			Integrator::Verlet => integrate_verlet(state, t, dt, cfg),
+			Integrator::Leapfrog => integrate_leapfrog(state, t, dt, cfg),
+			Integrator::Dopri => integrate_dopri(state, t, dt, cfg).0,
+			Integrator::Yoshida => integrate_yoshida(state, t, dt, cfg),
+		}
+	}
+
+	/* Of the error estimate used for adaptive steps */
+	fn order(self) -> u32 {
+		match self {
+			Integrator::Rk4 | Integrator::Dopri | Integrator::Yoshida => 4,
+			Integrator::Verlet | Integrator::Leapfrog => 2,
+		}
+	}
+}
+
+/*
+ * Take one step of at most *dt, shrinking it until the error is under tol. The error is estimated by
+ * comparing one whole step against two half steps, or for Dormand-Prince against its embedded
+ * fourth-order result. Returns the new state and the step actually taken, and leaves a suggestion
+ * for the next step, no larger than max_dt (or cfg.max_dt), in *dt.
+ */
+fn integrate_adaptive(state: &[Object], t: f32, dt: &mut f32, max_dt: f32, tol: f32, cfg: &SimConfig)
+	-> (Vec<Object>, f32) {
+	const SAFETY: f32 = 0.9;
+	const MAX_GROW: f32 = 2.0;
+	const MAX_SHRINK: f32 = 0.1;
+	let max_dt = cfg.max_dt.map_or(max_dt, |m| m.min(max_dt));
+	/* Don't grind to a halt on a true collision */
+	let min_dt = cfg.min_dt.unwrap_or(max_dt * 1.0e-6).min(max_dt);
+	let order = cfg.integrator.order();
+
+	loop {
+		let h = dt.min(max_dt);
+		let (fine, coarse) = match cfg.integrator {
+			Integrator::Dopri => integrate_dopri(state, t, h, cfg),
+			_ => {
+				let step = |s: &[Object], t, h| cfg.integrator.step(s, t, h, cfg);
+				let mid = step(state, t, 0.5 * h);
+				(step(mid.as_slice(), t + 0.5 * h, 0.5 * h), step(state, t, h))
+			},
+		};
+
+		/* How far off each body ended up, relative to how far it moved */
+		let err = state.iter()
+			.zip(coarse.iter().zip(fine.iter()))
+			.map(|(o, (a, b))| {
+				let moved = (b.s - o.s).norm::<f32>();
+				if moved == 0.0 { 0.0 } else { a.s.distance::<f32>(b.s) / moved }
+			})
+			.fold(0.0, f32::max);
+
+		/* Widely separated bodies may have no measurable error at all */
+		let factor = if err == 0.0 {
+			MAX_GROW
+		} else {
+			(SAFETY * (tol / err).powf(1.0 / (order + 1) as f32)).max(MAX_SHRINK).min(MAX_GROW)
+		};
+		if err <= tol || h <= min_dt {
+			*dt = (h * factor).min(max_dt);
+			return (fine, h);
+		}
+		*dt = (h * factor).max(min_dt);
+	}
+}
+
+/*
+ * The bodies with their velocities in step with their positions. With fixed-step leapfrog, the
+ * velocities are kept half a step of lead ahead; the positions haven't moved since their last
+ * kick, so the acceleration cache makes bringing them back free.
+ */
+fn synced(state: &[Object], t: f32, lead: Option<f32>, cfg: &SimConfig) -> Vec<Object> {
+	match lead {
+		Some(h) => leapfrog_sync(state, t, h, cfg),
+		None => state.to_vec(),
+	}
+}
+
+/*
+ * Bring leapfrog's velocities back in step before the bodies are edited by hand, so the edit
+ * doesn't land half a step off; the next tick then staggers them afresh
+ */
+fn unstagger(state: &mut Vec<Object>, t: f32, lead: &mut Option<f32>, cfg: &SimConfig) {
+	if lead.is_some() {
+		*state = synced(state.as_slice(), t, lead.take(), cfg);
+	}
+}
+
+/*
+ * Advance the simulation (and t) by h, including any collisions along the way. With fixed-step
+ * leapfrog, this leaves the velocities half a step ahead and records that step in *lead; see
+ * synced. Returns the (ascending) indices of any bodies which were merged away or escaped.
+ */
+fn tick(state: &mut Vec<Object>, t: &mut f32, h: f32, step_dt: &mut f32, lead: &mut Option<f32>,
+	cfg: &SimConfig) -> Vec<usize> {
+	let leaping = cfg.integrator == Integrator::Leapfrog && cfg.tol.is_none();
+	if !leaping {
+		if let Some(old) = lead.take() {
+			*state = leapfrog_sync(state.as_slice(), *t, old, cfg);
+		}
+	}
+	/* Only warn when a body first gets far enough away */
+	let was_far = cfg.escape.map(|dist| beyond(state.as_slice(), dist));
+	match cfg.tol {
+		Some(tol) => {
+			/* Cover the same span of time in however many steps it takes */
+			let mut left = h;
+			while left > 0.0 {
+				let mut dt = step_dt.min(left);
+				let (new, taken) = integrate_adaptive(state.as_slice(), *t, &mut dt, h, tol, cfg);
+				*state = new;
+				*t += taken;
+				left -= taken;
+				*step_dt = dt;
+			}
+		},
+		None if leaping => {
+			/* Get half a step ahead, or make up the difference if the step has changed */
+			let ahead = match *lead {
+				None => leapfrog_init(state.as_slice(), *t, h, cfg),
+				Some(old) if old != h => kick(state.as_slice(), *t, 0.5 * (h - old), cfg),
+				Some(_) => state.clone(),
+			};
+			*state = leapfrog_step(ahead.as_slice(), *t, h, cfg);
+			*lead = Some(h);
+			*t += h;
+		},
+		None => {
+			*state = cfg.integrator.step(state.as_slice(), *t, h, cfg);
+			*t += h;
+		},
+	}
+	if let Some(l) = cfg.period {
+		for o in state.iter_mut() {
+			o.s = wrap(o.s, l);
+		}
+	}
+
+	let mut removed = Vec::new();
+	if let (Some(dist), Some(was_far)) = (cfg.escape, was_far) {
+		let gone = escaping(state.as_slice(), cfg, dist);
+		for &i in &gone {
+			if cfg.remove_escaped {
+				eprintln!("t = {}: removing escaped body {}", t, i);
+			} else if !was_far[i] {
+				eprintln!("t = {}: body {} is escaping", t, i);
+			}
+		}
+		if cfg.remove_escaped {
+			let mut i = 0;
+			state.retain(|_| {
+				i += 1;
+				!gone.contains(&(i - 1))
+			});
+			removed = gone;
+		}
+	}
+
+	let merged = match cfg.collisions {
+		Collisions::Elastic => {
+			collide(state.as_mut_slice(), cfg.restitution);
+			Vec::new()
+		},
+		Collisions::Merge => merge(state),
+	};
+	if merged.is_empty() {
+		return removed;
+	}
+	/* Merging happened after the escapees left, so map its indices back to before then */
+	let survivors = (0..state.len() + merged.len() + removed.len())
+		.filter(|i| !removed.contains(i))
+		.collect::<Vec<_>>();
+	removed.extend(merged.iter().map(|&i| survivors[i]));
+	removed.sort();
+	removed
+}
+
+/* Run steps ticks of h without a window, returning where everything ended up */
+fn run_headless(mut state: Vec<Object>, mut masses: MassSchedules, steps: usize, h: f32,
+	cfg: &SimConfig, energy_log: &mut Option<EnergyLog>, trajectory_log: &mut Option<TrajectoryLog>)
+	-> Vec<Object> {
+	let mut cfg = *cfg;
+	let mut t = 0.0;
+	let mut step_dt = h;
+	let mut lead = None;
+	for _ in 0..steps {
+		let t0 = t;
+		let removed = tick(&mut state, &mut t, h, &mut step_dt, &mut lead, &cfg);
+		masses.remove(removed.as_slice());
+		masses.apply(state.as_mut_slice(), t0, t);
+		cfg.remove(removed.as_slice());
+		if energy_log.is_none() && trajectory_log.is_none() {
+			continue;
+		}
+		let now = synced(state.as_slice(), t, lead, &cfg);
+		if let Some(ref mut log) = *energy_log {
+			log.record(t, now.as_slice(), &cfg).expect("cannot write energy log");
+		}
+		if let Some(ref mut log) = *trajectory_log {
+			log.record(t, now.as_slice()).expect("cannot write trajectory log");
+		}
+	}
+	synced(state.as_slice(), t, lead, &cfg)
+}
+
+/*
+ * Run a circular binary for one period and compare where it ends up with where it started,
+ * returning whether the error is within tolerance
+ */
+fn check(cfg: &SimConfig) -> bool {
+	const STEPS: usize = 1000;
+	const TOL: f32 = 1.0e-3;
+	/* In units where the orbit is about 1 across */
+	let cfg = SimConfig {
+		period: None,
+		..*cfg
+	};
+	let (init, period) = circular_binary(1.0 / cfg.g, 1.0, cfg.g);
+
+	let end = run_headless(init.clone(), MassSchedules::default(), STEPS, period / STEPS as f32, &cfg, &mut None, &mut None);
+	let err = init.iter()
+		.zip(end.iter())
+		.map(|(a, b)| a.s.distance::<f32>(b.s))
+		.fold(0.0, f32::max);
+	println!("relative position error after one period: {:e} (tolerance {:e})", err, TOL);
+	err <= TOL
+}
+
+/*
+ * Run the same scene under RK4 and Verlet side by side, printing how far apart the bodies have
+ * drifted and each run's energy after every tick
+ */
+fn compare(state: Vec<Object>, masses: MassSchedules, steps: usize, h: f32, cfg: &SimConfig) {
+	let mut cfgs = [
+		SimConfig { integrator: Integrator::Rk4, ..*cfg },
+		SimConfig { integrator: Integrator::Verlet, ..*cfg },
+	];
+	let mut runs = vec![(state.clone(), masses.clone(), 0.0, h), (state, masses, 0.0, h)];
+	println!("t\tmax_ds\tE_rk4\tE_verlet");
+	for _ in 0..steps {
+		for (run, cfg) in runs.iter_mut().zip(cfgs.iter_mut()) {
+			let (ref mut state, ref mut masses, ref mut t, ref mut step_dt) = *run;
+			let t0 = *t;
+			let removed = tick(state, t, h, step_dt, &mut None, cfg);
+			masses.remove(removed.as_slice());
+			masses.apply(state.as_mut_slice(), t0, *t);
+			cfg.remove(removed.as_slice());
+		}
+		let (ref a, _, t, _) = runs[0];
+		let (ref b, _, _, _) = runs[1];
+		let ds = a.iter()
+			.zip(b.iter())
+			.map(|(a, b)| a.s.distance::<f32>(b.s))
+			.fold(0.0, f32::max);
+		let energy = |s: &[Object]| kinetic_energy(s) + potential_energy(s, cfg);
+		println!("{}\t{:e}\t{:e}\t{:e}", t, ds, energy(a.as_slice()), energy(b.as_slice()));
+	}
+}
+
+/* Whether the simulation is running, and how many single steps are owed while it isn't */
+#[derive(Debug, Default)]
+struct Playback {
+	paused: bool,
+	steps: u32,
+}
+
+impl Playback {
+	/* Whether to take one single step now */
+	fn take_step(&mut self) -> bool {
+		if self.paused && self.steps > 0 {
+			self.steps -= 1;
+			true
+		} else {
+			false
+		}
+	}
+}
+
+/*
+ * How many steps of dt to take for acc (real) seconds of lag, and how much lag is left over.
+ * When that's more than cap steps, the remainder is dropped instead of piling up.
+ */
+fn advance(acc: f32, dt: f32, cap: u32) -> (u32, f32) {
+	let steps = (acc / dt).floor();
+	if steps >= cap as f32 {
+		(cap, 0.0)
+	} else {
+		(steps as u32, acc - steps * dt)
+	}
+}
+
+/* Add a new body to the simulation along with its sprite, returning its index */
+fn add_object(state: &mut Vec<Object>, gfx: &mut Vec<RcSprite>, names: &mut Vec<String>, o: Object,
+	sprite: RcSprite) -> usize {
+	state.push(o);
+	gfx.push(sprite);
+	names.push(String::new());
+	state.len() - 1
+}
+
+/*
+ * Drop the sprites and names of bodies removed from the simulation, and fix up the selection to
+ * match
+ */
+fn drop_removed(gfx: &mut Vec<RcSprite>, names: &mut Vec<String>, selected: &mut Option<usize>,
+	removed: &[usize]) {
+	for &i in removed.iter().rev() {
+		gfx.remove(i);
+		names.remove(i);
+	}
+	*selected = selected.and_then(|s| remap(s, removed));
+}
+
+/* Where body i ends up once the (ascending) removed bodies are gone, if it's still there */
+fn remap(i: usize, removed: &[usize]) -> Option<usize> {
+	if removed.contains(&i) {
+		None
+	} else {
+		Some(i - removed.iter().filter(|&&r| r < i).count())
+	}
+}
+
+/* Grow or shrink a body's mass by a factor, keeping it positive */
+fn scale_mass(o: &mut Object, factor: f32) {
+	const MIN_MASS: f32 = 1.0e-30;
+	o.m = (o.m * factor).max(MIN_MASS);
+}
+
+/* The index of the body nearest to at, if any is within tol */
+fn pick(at: vec2<f32>, state: &[Object], tol: f32) -> Option<usize> {
+	state.iter()
+		.enumerate()
+		.map(|(i, o)| (i, (o.s - at).normsq()))
+		.filter(|&(_, d)| d <= tol * tol)
+		.min_by(|&(_, a), &(_, b)| a.partial_cmp(&b).unwrap())
+		.map(|(i, _)| i)
+}
+
+/* A new body at from, flung so that it would cover the drag to `to` in time */
+fn spawn(from: vec2<f32>, to: vec2<f32>, m: f32, time: f32) -> Object {
+	Object {
+		s: from,
+		v: (to - from) * (1.0 / time),
+		m,
+		r: 0.0,
+		fixed: false,
+	}
+}
+
+/* Simulated seconds in the largest unit that shows at least 1.0 once rounded */
+fn format_sim_time(t: f32) -> String {
+	const UNITS: [(f32, &'static str); 5] = [
+		(365.25 * 86400.0, "yr"),
+		(86400.0, "d"),
+		(3600.0, "h"),
+		(60.0, "min"),
+		(1.0, "s"),
+	];
+	/* Round first, so 59.99 s shows as 1.0 min rather than 60.0 s */
+	let round = |x: f32| (x * 10.0).round() / 10.0;
+	let &(unit, name) = UNITS.iter()
+		.find(|&&(unit, _)| round(t.abs() / unit) >= 1.0)
+		.unwrap_or(&UNITS[UNITS.len() - 1]);
+	format!("{:.1} {}", round(t / unit), name)
+}
+
+/* Blue for a body at rest, shading to red for the fastest body */
+fn speed_color(speed: f32, max: f32) -> Color {
+	let k = if max > 0.0 { (speed / max).max(0.0).min(1.0) } else { 0.0 };
+	Color::rgb((255.0 * k) as u8, 0, (255.0 * (1.0 - k)) as u8)
+}
+
+/* A filled circle of the given radius (in pixels) on a transparent background */
+fn disc(radius: u32, color: &Color) -> Image {
+	let d = 2 * radius;
+	let mut img = Image::from_color(d, d, &Color::transparent()).expect("cannot create disc image");
+	let c = radius as f32 - 0.5;
+	for x in 0..d {
+		for y in 0..d {
+			if vec2::new(x as f32 - c, y as f32 - c).normsq() <= (radius * radius) as f32 {
+				img.set_pixel(x, y, color);
+			}
+		}
+	}
+	img
+}
+
+/*
+ * How much to scale a w by h texture so it spans a body's diameter, but never less than floor (in
+ * world units) across
+ */
+fn sprite_scale(radius: f32, w: u32, h: u32, floor: f32) -> f32 {
+	(2.0 * radius).max(floor) / w.max(h).max(1) as f32
+}
+
+/* A few colors to tell untextured bodies apart */
+fn disc_color(i: usize) -> Color {
+	match i % 6 {
+		0 => Color::white(),
+		1 => Color::rgb(255, 200, 64),
+		2 => Color::rgb(64, 160, 255),
+		3 => Color::rgb(255, 96, 96),
+		4 => Color::rgb(96, 255, 128),
+		_ => Color::rgb(200, 128, 255),
+	}
+}
+
+/*
+ * The texture for body i, loading it if it hasn't been already. Bodies without a texture (or whose
+ * texture won't load) get a colored disc instead.
+ */
+fn texture(cache: &mut HashMap<String, Rc<Texture>>, path: &str, i: usize) -> Rc<Texture> {
+	const DISC_RADIUS: u32 = 4;
+
+	if !path.is_empty() {
+		if let Some(tex) = cache.get(path) {
+			return tex.clone();
+		}
+		match Image::from_file(path) {
+			Some(img) => {
+				img.create_mask_from_color(&Color::black(), 0);
+				let tex = Rc::new(Texture::from_image(&img).expect("could not convert image to texture"));
+				cache.insert(path.into(), tex.clone());
+				return tex;
+			},
+			None => eprintln!("cannot load texture from {}, drawing a disc instead", path),
+		}
+	}
+
+	let key = format!("#disc{}", i % 6);
+	cache.entry(key)
+		.or_insert_with(|| Rc::new(Texture::from_image(&disc(DISC_RADIUS, &disc_color(i)))
+			.expect("could not convert image to texture")))
+		.clone()
+}
+
+const USAGE: &'static str = "\
+usage: nbody [options] [scene]
+
+Reads a scene from the given path (or stdin); scenes ending in .json are JSON.
+
+Running:
+  --dt T                  simulated time per step
+  --time-mult K           simulated time per second of real time
+  --paused                start paused
+  --size WxH              window size, in pixels (default: the whole desktop)
+  --headless N            take N steps without a window and print the result
+  --compare N             take N steps with RK4 and Verlet and print how they differ
+  --check                 run the self-checks and exit
+  --random N              simulate N bodies in a Plummer sphere instead of a scene
+  --seed S                seed for --random
+  --max-substeps N        steps to catch up on per frame before dropping time
+  --max-lag T             real seconds of lag to catch up on before dropping time
+
+Physics:
+  --integrator NAME       rk4, verlet, leapfrog, dopri, or yoshida
+  --verlet, --leapfrog, --dopri
+  --adaptive TOL          adapt the step size to keep the error under TOL
+  --min-dt T, --max-dt T  limits on adaptive steps
+  --grav-const G          gravitational constant
+  --softening EPS         Plummer softening length (default 0)
+  --light-speed C         add post-Newtonian corrections
+  --drag \"LIN QUAD [BODY SCALE]\"
+  --background POTENTIAL  point M, harmonic W, nfw M RS, or miyamoto-nagai M A B
+  --periodic              wrap around the edges of the universe
+  --escape K              watch for bodies escaping past K universe radii
+  --remove-escaped        and remove them
+  --merge                 merge colliding bodies instead of bouncing them
+  --restitution E         coefficient of restitution for bounces
+  --scale-with-mass       grow and shrink radii along with mass
+  --recenter              move to the center of mass frame
+  --virial Q              scale velocities to a virial ratio of Q
+
+Performance:
+  --threads N             worker threads (default: one per core)
+  --serial-below N        sum forces serially below N bodies
+  --bh-above N            use Barnes-Hut above N bodies
+  --theta ANGLE           Barnes-Hut opening angle
+  --simd                  sum every pair with the AVX kernel, at any body count
+  --gpu                   sum forces on the GPU
+  --profile               time each phase of a frame
+
+Output:
+  --energy-csv PATH       log conserved quantities
+  --energy-every N        only every N steps
+  --csv PATH              log trajectories
+  --record N              save a screenshot every N frames
+  --help                  show this message
+";
+
+/* Complain about the command line, and give up */
+fn usage_error(msg: &str) -> ! {
+	eprint!("{}\n\n{}", msg, USAGE);
+	process::exit(2);
+}
+
+/* The value following flag on the command line, which should be what */
+fn arg_value<T: FromStr>(args: &mut Iterator<Item = String>, flag: &str, what: &str) -> T {
+	let value = args.next().unwrap_or_else(|| usage_error(&format!("{} requires {}", flag, what)));
+	value.parse()
+		.unwrap_or_else(|_| usage_error(&format!("invalid {} for {}: `{}'", what, flag, value)))
 }
 
 fn main() {
-	
-	let mut window = RenderWindow::new(VideoMode::desktop_mode(), "nbody", style::DEFAULT,
-		&ContextSettings::default());
+	let mut cfg = SimConfig::default();
+	let mut energy_log = None;
+	let mut energy_every = 1;
+	let mut trajectory_log = None;
+	let mut profile = None;
+	let mut threads = 0;
+	let mut recenter = false;
+	let mut scene = None;
+	let mut headless = None;
+	let mut random = None;
+	let mut seed = 0;
+	let mut g = None;
+	let mut periodic = false;
+	let mut virial = None;
+	let mut integrator = None;
+	let mut tol = None;
+	let mut eps = None;
+	let mut c = None;
+	let mut drag = None;
+	let mut background = None;
+	let mut restitution = None;
+	let mut scale_with_mass = false;
+	let mut comparing = None;
+	let mut max_substeps = 5;
+	let mut max_lag = 0.25;
+	let mut escape = None;
+	let mut record_every = 0;
+	let mut self_check = false;
+	let mut timestep = None;
+	let mut time_mult = None;
+	let mut paused = false;
+	let mut size = None;
+	let mut args = env::args().skip(1);
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--energy-csv" => {
+				let path: String = arg_value(&mut args, "--energy-csv", "a path");
+				energy_log = Some(EnergyLog::create(&path).unwrap_or_else(|e|
+					usage_error(&format!("cannot create energy log {}: {}", path, e))));
+			},
+			"--energy-every" =>
+				energy_every = arg_value(&mut args, "--energy-every", "a step count"),
+			"--recenter" => recenter = true,
+			"--virial" => virial = Some(arg_value(&mut args, "--virial", "a virial ratio")),
+			"--csv" => {
+				let path: String = arg_value(&mut args, "--csv", "a path");
+				trajectory_log = Some(TrajectoryLog::create(&path).unwrap_or_else(|e|
+					usage_error(&format!("cannot create trajectory log {}: {}", path, e))));
+			},
+			"--headless" => headless = Some(arg_value(&mut args, "--headless", "a step count")),
+			"--random" => random = Some(arg_value(&mut args, "--random", "a body count")),
+			"--grav-const" =>
+				g = Some(arg_value(&mut args, "--grav-const", "a gravitational constant")),
+			"--seed" => seed = arg_value(&mut args, "--seed", "a seed"),
+			"--profile" => profile = Some(Profile::new()),
+			"--threads" => threads = arg_value(&mut args, "--threads", "a thread count"),
+			"--serial-below" =>
+				cfg.serial_below = arg_value(&mut args, "--serial-below", "a body count"),
+			"--softening" => eps = Some(arg_value(&mut args, "--softening", "a softening length")),
+			"--bh-above" => cfg.bh_above = arg_value(&mut args, "--bh-above", "a body count"),
+			"--simd" => cfg.simd = true,
+			"--theta" => cfg.theta = arg_value(&mut args, "--theta", "an opening angle"),
+			"--adaptive" => tol = Some(arg_value(&mut args, "--adaptive", "a tolerance")),
+			"--verlet" => integrator = Some(Integrator::Verlet),
+			"--leapfrog" => integrator = Some(Integrator::Leapfrog),
+			"--dopri" => integrator = Some(Integrator::Dopri),
+			"--light-speed" => c = Some(arg_value(&mut args, "--light-speed", "a speed of light")),
+			"--drag" =>
+				drag = Some(arg_value(&mut args, "--drag", "\"linear quadratic [body scale]\"")),
+			"--background" =>
+				background = Some(arg_value(&mut args, "--background", "a potential")),
+			"--integrator" =>
+				integrator = Some(arg_value(&mut args, "--integrator", "an integrator")),
+			"--min-dt" => cfg.min_dt = Some(arg_value(&mut args, "--min-dt", "a time")),
+			"--max-dt" => cfg.max_dt = Some(arg_value(&mut args, "--max-dt", "a time")),
+			"--compare" => comparing = Some(arg_value(&mut args, "--compare", "a step count")),
+			"--check" => self_check = true,
+			"--record" => record_every = arg_value(&mut args, "--record", "a frame interval"),
+			"--periodic" => periodic = true,
+			"--max-substeps" =>
+				max_substeps = arg_value(&mut args, "--max-substeps", "a step count"),
+			"--max-lag" => max_lag = arg_value(&mut args, "--max-lag", "a time"),
+			"--escape" => escape = Some(arg_value::<f32>(&mut args, "--escape",
+				"a multiple of the universe radius")),
+			"--remove-escaped" => cfg.remove_escaped = true,
+			"--merge" => cfg.collisions = Collisions::Merge,
+			"--scale-with-mass" => scale_with_mass = true,
+			"--restitution" =>
+				restitution = Some(arg_value(&mut args, "--restitution", "a coefficient")),
+			"--dt" => timestep = Some(arg_value(&mut args, "--dt", "a time")),
+			"--time-mult" => time_mult = Some(arg_value(&mut args, "--time-mult", "a multiplier")),
+			"--paused" => paused = true,
+			"--size" => {
+				let wh: String = arg_value(&mut args, "--size", "WIDTHxHEIGHT");
+				let mut dims = wh.splitn(2, 'x').map(str::parse::<u32>);
+				size = match (dims.next(), dims.next()) {
+					(Some(Ok(w)), Some(Ok(h))) if w > 0 && h > 0 => Some((w, h)),
+					_ => usage_error(&format!("invalid WIDTHxHEIGHT for --size: `{}'", wh)),
+				};
+			},
+			"--help" | "-h" => {
+				print!("{}", USAGE);
+				return;
+			},
+			"--gpu" => if cfg!(feature = "gpu") {
+				cfg.gpu = true;
+			} else {
+				usage_error("built without GPU support; rebuild with --features gpu");
+			},
+			_ if !arg.starts_with("--") && scene.is_none() => scene = Some(arg),
+			_ => usage_error(&format!("unknown argument: `{}'", arg)),
+		}
+	}
+	/* Zero leaves it up to rayon */
+	rayon::initialize(rayon::Configuration::new().num_threads(threads))
+		.expect("cannot create thread pool");
+
+	if self_check {
+		/* There's no scene to merge with, so the command line is all there is */
+		if let Some(g) = g {
+			cfg.g = g;
+		}
+		cfg.eps = eps.unwrap_or(cfg.eps);
+		cfg.integrator = integrator.unwrap_or(cfg.integrator);
+		cfg.tol = tol;
+		process::exit(if check(&cfg) { 0 } else { 1 });
+	}
+
+	let (r, settings, mut state, paths, schedules, mut names) = match (random, scene) {
+		(Some(n), _) => {
+			/* About a solar mass spread over about an AU */
+			const A: f32 = 1.5e11;
+			Ok((5.0 * A, Settings::default(), plummer(n, 2.0e30, A, g.unwrap_or(G), seed),
+				vec![String::new(); n], vec![None; n], vec![String::new(); n]))
+		},
+		(None, Some(ref path)) => {
+			let file = File::open(path)
+				.unwrap_or_else(|e| usage_error(&format!("cannot open scene {}: {}", path, e)));
+			if path.ends_with(".json") {
+				parse_scene_json(BufReader::new(file))
+			} else {
+				parse_scene(BufReader::new(file))
+			}
+		},
+		(None, None) => {
+			let stdin = io::stdin();
+			let lock = stdin.lock();
+			parse_scene(lock)
+		},
+	}.unwrap_or_else(|e| usage_error(&format!("cannot parse scene: {}", e)));
+	let mut masses = MassSchedules::new(schedules);
+	masses.scale_radius = scale_with_mass;
+	/* The command line wins over the scene */
+	cfg.g = g.or(settings.g).unwrap_or(G);
+	cfg.integrator = integrator.or(settings.integrator).unwrap_or(cfg.integrator);
+	cfg.tol = tol.or(settings.tol);
+	cfg.eps = eps.or(settings.eps).unwrap_or(cfg.eps);
+	cfg.c = c.or(settings.c);
+	cfg.drag = drag.or(settings.drag);
+	cfg.background = background.or(settings.background);
+	cfg.restitution = restitution.or(settings.restitution).unwrap_or(cfg.restitution);
+	if periodic {
+		cfg.period = Some(2.0 * r);
+	}
+	cfg.escape = escape.map(|k| k * r);
+	if recenter {
+		recenter_momentum(state.as_mut_slice());
+		recenter_position(state.as_mut_slice());
+	}
+	/* After recentering, so the drift of the whole system doesn't count as kinetic energy */
+	if let Some(q) = virial {
+		if !scale_to_virial(state.as_mut_slice(), &cfg, q) {
+			eprintln!("cannot scale a scene with no motion or no gravity to a virial ratio");
+		}
+	}
+
+	let mut mult = time_mult.or(settings.time_mult).unwrap_or(1.0e6);
+	/* Steps are taken in real time, so a simulated timestep is spread over the multiplier */
+	let dt = timestep.or(settings.timestep).map(|h| h / mult).unwrap_or(1.0 / 1024.0);
+	if let Some(steps) = comparing {
+		compare(state, masses, steps, dt * mult, &cfg);
+		return;
+	}
+	/* Log where things start, so the drift is measured against it */
+	if let Some(ref mut log) = energy_log {
+		log.every(energy_every);
+		log.record(0.0, state.as_slice(), &cfg).expect("cannot write energy log");
+	}
+	if let Some(steps) = headless {
+		let state = run_headless(state, masses, steps, dt * mult, &cfg, &mut energy_log,
+			&mut trajectory_log);
+		println!("t = {}", steps as f32 * dt * mult);
+		if let Some((de, dp, dl)) = energy_log.as_ref().and_then(|log| log.drift()) {
+			println!("drift: E {:e} p {:e} L {:e}", de, dp, dl);
+		}
+		for o in &state {
+			println!("{} {} {} {} {} {}", o.s.x, o.s.y, o.v.x, o.v.y, o.m, o.r);
+		}
+		return;
+	}
+
+	let mode = size.map_or_else(VideoMode::desktop_mode, |(w, h)| VideoMode::new(w, h, 32));
+	let mut window = RenderWindow::new(mode, "nbody", style::DEFAULT, &ContextSettings::default());
 	window.set_framerate_limit(60);
 
-	let mut line = String::new();
-	io::stdin().read_line(&mut line);
-	line.pop();
-	let num_objs: usize = line.trim().parse().expect(&format!("invalid number of objects: `{}'", line));
-	line.clear();
-	io::stdin().read_line(&mut line);
-	line.trim();
-	let r: f32 = line.trim().parse().expect(&format!("invalid universe size: {}", line));
 	let mut view = View::new(Vector2f::new(0.0, 0.0), Vector2f::new(2.0 * r, 2.0 * r));
 	window.set_view(&view);
-	line.clear();
 
-	let mut state = Vec::new();
 	let mut tex_cache: RefCell<HashMap<String, _>> = RefCell::new(HashMap::new());
 	let mut gfx = Vec::new();
 	let def = window.default_view().size();
-	let mut tmp = Vec::new();
-	for i in 0..num_objs {
-		line.clear();
-		io::stdin().read_line(&mut line);
-		let mut iter = line.trim().split_whitespace();
-		state.push(Object {
-			s: vec2 {
-				x: {
-					let tmp_w = iter.next();
-					match tmp_w {
-						Some(tmp) => tmp.parse().expect(tmp),
-						None => continue
-					}
-				},
-				y: { let tmp = iter.next().unwrap(); tmp.parse().expect(tmp) },
-			},
-			v: vec2 {
-				x: { let tmp = iter.next().unwrap(); tmp.parse().expect(tmp) },
-				y: { let tmp = iter.next().unwrap(); tmp.parse().expect(tmp) },
-			},
-			m: iter.next().unwrap().parse().unwrap(),
-		});
-
-		let path = format!("img/{}", iter.next().unwrap().parse::<String>().unwrap());
-
-		preload_tex(&mut tex_cache.borrow_mut(), &path);
-		tmp.push(path);
-	}
-		
-	for path in tmp {
-		let tex = tex_cache.borrow().get(&path).unwrap().clone();
+	for (i, path) in paths.iter().enumerate() {
+		let tex = texture(&mut tex_cache.borrow_mut(), path, i);
 		let sz = tex.size();
 		let mut s = RcSprite::with_texture(tex);
 		s.set_origin((sz.x as f32 / 2.0, sz.y as f32 / 2.0));
-		s.scale((2.0 * r / def.x, 2.0 * r / def.y));
 		gfx.push(s);
 	}
+	/* What R goes back to */
+	let initial = (state.clone(), gfx.clone(), masses.clone(), names.clone(), cfg.drag);
 
 	let hack = Font::from_file("/usr/share/fonts/TTF/Hack-Regular.ttf").expect("cannot load Hack font");
 	let mut fps_counter = Text::default();
@@ -174,6 +1334,65 @@ fn main() {
 	fps_counter.set_position(window.map_pixel_to_coords_current_view(&Vector2i::new(0, 0)));
 	fps_counter.scale((2.0 * r / def.x, 2.0 * r / def.y));
 
+	/* Screen-space view for HUD panels */
+	let win_sz = window.size();
+	let hud = View::new(Vector2f::new(win_sz.x as f32 / 2.0, win_sz.y as f32 / 2.0),
+		Vector2f::new(win_sz.x as f32, win_sz.y as f32));
+	let mut show_energy = false;
+	let mut trails = Trails::new(64);
+	let mut energy_plot = Plot::new(512);
+	/* The exact potential energy sums every pair, so big scenes only refresh it now and then */
+	const PE_EVERY: u32 = 16;
+	let mut pe = 0.0;
+	let mut frames = 0u32;
+	let mut show_speeds = false;
+	let mut show_stats = false;
+	let mut mark_escaping = false;
+	let mut color_speeds = false;
+	let mut selected: Option<usize> = None;
+	/* Where a new body is being dragged out from and to, in view coordinates */
+	let mut drag: Option<(vec2<f32>, vec2<f32>)> = None;
+	let mut spawn_mass = if state.is_empty() {
+		1.0
+	} else {
+		state.iter().map(|o| o.m).sum::<f32>() / state.len() as f32
+	};
+	let mut apsides: Option<Apsides> = None;
+	let mut closure: Option<Closure> = None;
+	let mut measuring = false;
+	let mut ends: Vec<vec2<f32>> = Vec::new();
+	let mut measure_info = Text::default();
+	measure_info.set_font(&hack);
+	measure_info.set_character_size(16);
+	measure_info.set_position((win_sz.x as f32 - 320.0, win_sz.y as f32 - 40.0));
+	let mut show_ruler = true;
+	let mut show_grid = false;
+	let mut ruler_label = Text::default();
+	ruler_label.set_font(&hack);
+	ruler_label.set_character_size(14);
+	let mut cursor = None;
+	let mut cursor_info = Text::default();
+	cursor_info.set_font(&hack);
+	cursor_info.set_character_size(14);
+	cursor_info.set_position((10.0, win_sz.y as f32 - 160.0));
+	let mut body_frame = false;
+	let mut barycentric = false;
+	let mut auto_fit = false;
+	let aspect = win_sz.x as f32 / win_sz.y as f32;
+	let mut rotating = false;
+	let mut omega = 0.0;
+	/* Extra rotation of the view, on top of any rotating frame */
+	let mut view_angle = 0.0;
+	let mut frame = Frame::inertial();
+	let mut sel_info = Text::default();
+	sel_info.set_font(&hack);
+	sel_info.set_character_size(16);
+	sel_info.set_position((win_sz.x as f32 - 320.0, 200.0));
+	let mut stats = Text::default();
+	stats.set_font(&hack);
+	stats.set_character_size(16);
+	stats.set_position((win_sz.x as f32 - 320.0, 10.0));
+
 	let mut left = false;
 	let mut right = false;
 	let mut up = false;
@@ -181,15 +1400,29 @@ fn main() {
 	
 	let mut t = 0.0;
 	let mut acc = 0.0;
-	let mut mult = 1.0e6;
-	let mut dt = 1.0 / 1024.0;
+	/* Simulated time skipped because we couldn't keep up */
+	let mut dropped = 0.0;
+	let mut step_dt = dt * mult;
+	/* How far leapfrog's velocities are staggered ahead of the positions, if at all */
+	let mut lead = None;
+	let mut playback = Playback {
+		paused,
+		steps: 0,
+	};
+	let mut recorder = Recorder::new(record_every);
+	let mut snapshot = false;
 	let mut clk = Clock::start();
 
 	loop {
 
-		for evt in window.events() {
+		while let Some(evt) = window.poll_event() {
 			match evt {
-				Event::Closed => return,
+				Event::Closed => {
+					if let Some(ref mut log) = trajectory_log {
+						log.flush().expect("cannot write trajectory log");
+					}
+					return;
+				},
 				Event::KeyPressed {code, alt, ctrl, shift, system} => {
 					println!("{:?} pressed", code);
 					match code {
@@ -201,6 +1434,118 @@ fn main() {
 						Key::A => left = true,
 						Key::S => down = true,
 						Key::D => right = true,
+						Key::F1 => {
+							show_energy = !show_energy;
+							energy_plot.clear();
+						},
+						Key::F2 => show_speeds = !show_speeds,
+						Key::F3 => show_stats = !show_stats,
+						Key::F4 => mark_escaping = !mark_escaping,
+						Key::F5 => color_speeds = !color_speeds,
+						Key::F6 => show_grid = !show_grid,
+						/* Switch between Barnes-Hut and summing every pair, whatever the body count */
+						Key::F7 => {
+							let bh = cfg.summation(state.len()) == Summation::BarnesHut;
+							cfg.force_bh = Some(!bh);
+						},
+						Key::C => {
+							body_frame = !body_frame;
+							view.set_center((0.0, 0.0));
+						},
+						Key::B => {
+							barycentric = !barycentric;
+							view.set_center((0.0, 0.0));
+						},
+						Key::J => {
+							let len = trails.len() / 2;
+							trails.set_len(len);
+						},
+						Key::K => {
+							let len = (trails.len() * 2).max(1);
+							trails.set_len(len);
+						},
+						Key::P => snapshot = true,
+						Key::Space => playback.paused = !playback.paused,
+						Key::N => if playback.paused {
+							playback.steps += 1;
+						},
+						Key::R => {
+							state = initial.0.clone();
+							gfx = initial.1.clone();
+							masses = initial.2.clone();
+							names = initial.3.clone();
+							cfg.drag = initial.4;
+							lead = None;
+							t = 0.0;
+							acc = 0.0;
+							dropped = 0.0;
+							trails = Trails::new(trails.len());
+							selected = None;
+							apsides = None;
+							closure = None;
+						},
+						Key::Tab => if !state.is_empty() {
+							selected = Some(selected.map_or(0, |i| (i + 1) % state.len()));
+						},
+						Key::V => {
+							unstagger(&mut state, t, &mut lead, &cfg);
+							recenter_momentum(state.as_mut_slice());
+						},
+						Key::O => rotating = !rotating,
+						Key::F => {
+							let (lo, hi) = framed_box(state.as_slice(), &frame);
+							let sz = view.size();
+							let (c, sz) = fit(lo, hi, aspect, vec2::new(sz.x, sz.y));
+							approach(&mut view, c, sz, 1.0);
+						},
+						Key::Z => auto_fit = !auto_fit,
+						Key::L => show_ruler = !show_ruler,
+						Key::Up | Key::Down | Key::Left | Key::Right => if let Some(i) = selected {
+							unstagger(&mut state, t, &mut lead, &cfg);
+							let o = state[i];
+							let v_esc = escape_velocity(o.s, state.as_slice(), &cfg);
+							let dv = 0.01 * o.v.norm::<f32>().max(v_esc);
+							/* Screen directions, mapped back into the world */
+							let d = match code {
+								Key::Up => vec2::new(0.0, -dv),
+								Key::Down => vec2::new(0.0, dv),
+								Key::Left => vec2::new(-dv, 0.0),
+								_ => vec2::new(dv, 0.0),
+							};
+							state[i].v = o.v + frame.unapply_dir(d);
+						},
+						Key::G => {
+							unstagger(&mut state, t, &mut lead, &cfg);
+							cfg.gravity = !cfg.gravity;
+						},
+						Key::H => if let Some(i) = selected {
+							unstagger(&mut state, t, &mut lead, &cfg);
+							state[i].fixed = !state[i].fixed;
+						},
+						Key::Insert => if let Some(i) = selected {
+							unstagger(&mut state, t, &mut lead, &cfg);
+							/* Drop the copy just to the right of the original */
+							let mut o = state[i];
+							o.s = o.s + frame.unapply_dir(vec2::new(16.0 * view.size().x / win_sz.x as f32, 0.0));
+							let sprite = gfx[i].clone();
+							selected = Some(add_object(&mut state, &mut gfx, &mut names, o, sprite));
+						},
+						Key::Equal => if let Some(i) = selected {
+							unstagger(&mut state, t, &mut lead, &cfg);
+							scale_mass(&mut state[i], 1.1);
+						},
+						Key::Dash => if let Some(i) = selected {
+							unstagger(&mut state, t, &mut lead, &cfg);
+							scale_mass(&mut state[i], 1.0 / 1.1);
+						},
+						Key::M => {
+							measuring = !measuring;
+							ends.clear();
+						},
+						Key::Q => view_angle += std::f32::consts::PI / 36.0,
+						Key::E => view_angle -= std::f32::consts::PI / 36.0,
+						Key::LBracket => omega -= 1.0e-7,
+						Key::RBracket => omega += 1.0e-7,
 						_ => {},
 					}
 				},
@@ -214,6 +1559,52 @@ fn main() {
 						_ => {}
 					}
 				},
+				Event::MouseWheelScrolled {delta, ..} => match (drag, selected) {
+					(None, Some(i)) => {
+						unstagger(&mut state, t, &mut lead, &cfg);
+						scale_mass(&mut state[i], 1.1f32.powf(delta));
+					},
+					_ => spawn_mass *= 1.1f32.powf(delta),
+				},
+				Event::MouseMoved {x, y} => {
+					cursor = Some(Vector2i::new(x, y));
+					if let Some((from, _)) = drag {
+						let at = window.map_pixel_to_coords_current_view(&Vector2i::new(x, y));
+						drag = Some((from, vec2::new(at.x, at.y)));
+					}
+				},
+				Event::MouseButtonPressed {button: mouse::Button::Left, x, y} => {
+					let at = window.map_pixel_to_coords_current_view(&Vector2i::new(x, y));
+					if measuring {
+						if ends.len() == 2 {
+							ends.clear();
+						}
+						ends.push(vec2::new(at.x, at.y));
+						continue;
+					}
+					/* Allow clicks within a few pixels of a body */
+					let tol = 8.0 * view.size().x / win_sz.x as f32;
+					selected = pick(frame.unapply(vec2::new(at.x, at.y)), state.as_slice(), tol);
+					apsides = None;
+					closure = None;
+					/* Clicking on empty space starts dragging out a new body */
+					if selected.is_none() {
+						let at = vec2::new(at.x, at.y);
+						drag = Some((at, at));
+					}
+				},
+				Event::MouseButtonReleased {button: mouse::Button::Left, ..} => {
+					if let Some((from, to)) = drag.take() {
+						/* Dragging out the whole screen flings it across the screen in a second */
+						unstagger(&mut state, t, &mut lead, &cfg);
+						let o = spawn(frame.unapply(from), frame.unapply(to), spawn_mass, mult);
+						let tex = texture(&mut tex_cache.borrow_mut(), "", state.len());
+						let sz = tex.size();
+						let mut s = RcSprite::with_texture(tex);
+						s.set_origin((sz.x as f32 / 2.0, sz.y as f32 / 2.0));
+						selected = Some(add_object(&mut state, &mut gfx, &mut names, o, s));
+					}
+				},
 				_ => {},
 			}
 		}
@@ -225,27 +1616,763 @@ fn main() {
 		window.set_view(&view);
 		
 		let frame_time = clk.restart().as_seconds();
-		acc += frame_time;
+		if !playback.paused {
+			/* e.g. after dragging the window around */
+			let lag = acc + frame_time;
+			acc = lag.min(max_lag);
+			dropped += (lag - acc) * mult;
+		}
 		
+		let mut phase = Clock::start();
+		let (steps, left) = advance(acc, dt, max_substeps);
+		dropped += (acc - steps as f32 * dt - left) * mult;
+		acc = left;
 		let mut i = 0;
-		while acc >= dt && i < 5 {
-			state = integrate(state.as_slice(), t, dt * mult);
-			acc -= dt;
-			t += dt * mult;
+		while i < steps || playback.take_step() {
+			let t0 = t;
+			let removed = tick(&mut state, &mut t, dt * mult, &mut step_dt, &mut lead, &cfg);
+			masses.remove(removed.as_slice());
+			masses.apply(state.as_mut_slice(), t0, t);
+			cfg.remove(removed.as_slice());
+			if !removed.is_empty() {
+				drop_removed(&mut gfx, &mut names, &mut selected, removed.as_slice());
+				trails.remove(removed.as_slice());
+				apsides = None;
+				closure = None;
+			}
 			i += 1;
+			if energy_log.is_none() && trajectory_log.is_none() {
+				continue;
+			}
+			let now = synced(state.as_slice(), t, lead, &cfg);
+			if let Some(ref mut log) = energy_log {
+				log.record(t, now.as_slice(), &cfg).expect("cannot write energy log");
+			}
+			if let Some(ref mut log) = trajectory_log {
+				log.record(t, now.as_slice()).expect("cannot write trajectory log");
+			}
 		}
+		let integrate_time = phase.restart().as_seconds();
+		/* The rest of the frame only looks at the bodies, so it wants velocities in step */
+		let state = synced(state.as_slice(), t, lead, &cfg);
 		
+		/*
+		 * When rotating, co-rotate with the selected body and its primary about their barycenter
+		 * so that e.g. Lagrange points stay put. Without a pair, spin at a fixed rate instead.
+		 */
+		let pair = selected.and_then(|i| primary_of(i, state.as_slice()).map(|p| (i, p)));
+		frame = Frame::inertial();
+		if rotating {
+			match pair {
+				Some((i, p)) => {
+					let (a, b) = (&state[i], &state[p]);
+					let r = a.s - b.s;
+					frame.origin = (a.s * a.m + b.s * b.m) * (1.0 / (a.m + b.m));
+					frame.angle = r.y.atan2(r.x);
+				},
+				None => frame.angle = omega * t,
+			}
+		}
+		if barycentric {
+			frame.origin = center_of_mass(state.as_slice());
+		}
+		if body_frame {
+			if let Some(i) = selected {
+				frame.origin = state[i].s;
+			}
+		}
+		/* Trails follow the frame as it was at each sample, but turn along with the view */
+		trails.push(state.as_slice(), &frame);
+		let mut turn = Frame::inertial();
+		turn.angle = view_angle;
+		frame.angle += view_angle;
+
+		if auto_fit {
+			/* Ease towards the target so one fast body doesn't jerk the camera around */
+			let (lo, hi) = framed_box(state.as_slice(), &frame);
+			let sz = view.size();
+			let (c, sz) = fit(lo, hi, aspect, vec2::new(sz.x, sz.y));
+			approach(&mut view, c, sz, 0.05);
+			window.set_view(&view);
+		}
+
 		window.clear(&Color::black());
-		
+
+		if show_grid {
+			/* Matches the ruler, so its label gives the grid spacing too */
+			let step = nice_length(view.size().x / 5.0);
+			draw_grid(&mut window, view.center(), view.size(), step, Color::rgba(255, 255, 255, 32));
+		}
+		trails.draw(&mut window, &turn, Color::rgb(128, 128, 128));
+		let max_speed = state.iter().map(|o| o.v.norm::<f32>()).fold(0.0, f32::max);
+		/* Keep tiny bodies big enough to see and click on */
+		let floor = 6.0 * view.size().x / win_sz.x as f32;
 		for (o, mut s) in state.iter().zip(gfx.iter_mut()) {
-			s.set_position((o.s.x, o.s.y));
+			let at = frame.apply(o.s);
+			s.set_position((at.x, at.y));
+			let rect = s.texture_rect();
+			let k = sprite_scale(o.r, rect.width as u32, rect.height as u32, floor);
+			s.set_scale((k, k));
+			if mark_escaping && !is_bound(o, state.as_slice(), &cfg) {
+				s.set_color(&Color::red());
+			} else if color_speeds {
+				s.set_color(&speed_color(o.v.norm::<f32>(), max_speed));
+			} else {
+				s.set_color(&Color::white());
+			}
 			let sprite: &Sprite = &*s;
 			window.draw(sprite)
 		}
 
-		fps_counter.set_string(&format!("{:.0}\n{}", 1.0 / frame_time, mult));
+		let fresh = frames % PE_EVERY == 0 || state.len() < cfg.serial_below;
+		frames = frames.wrapping_add(1);
+		if fresh {
+			pe = potential_energy(state.as_slice(), &cfg);
+		}
+		let ke = kinetic_energy(state.as_slice());
+		let p = total_momentum(state.as_slice());
+		/* About whatever the view is centered on */
+		let l = if barycentric {
+			angular_momentum_about(state.as_slice(), center_of_mass(state.as_slice()))
+		} else {
+			angular_momentum(state.as_slice())
+		};
+		fps_counter.set_string(&format!(
+			"{:.0}\n{}\nt: {}{}{}\nKE: {:.4e}\nPE: {:.4e}\nE: {:.4e}\np: ({:.4e}, {:.4e})\nL: {:.4e}{}{}",
+			1.0 / frame_time, mult, format_sim_time(t),
+			if dropped > 0.0 { format!(" ({} dropped)", format_sim_time(dropped)) } else { String::new() },
+			if cfg.tol.is_some() { format!("\nstep: {}", format_sim_time(step_dt)) } else { String::new() },
+			ke, pe, ke + pe, p.x, p.y, l,
+			if cfg.gravity { "" } else { "\ngravity off" },
+			if !cfg.gpu && cfg.summation(state.len()) == Summation::BarnesHut {
+				"\nBarnes-Hut"
+			} else {
+				""
+			}));
 		window.draw(&fps_counter);
 
+		if show_energy {
+			if fresh {
+				energy_plot.push(ke + pe);
+			}
+			window.set_view(&hud);
+			energy_plot.draw(&mut window, Vector2f::new(10.0, win_sz.y as f32 - 130.0),
+				Vector2f::new(300.0, 120.0), Color::green());
+			window.set_view(&view);
+		}
+
+		if show_speeds {
+			window.set_view(&hud);
+			draw_bars(&mut window, speed_histogram(state.as_slice(), 32).as_slice(),
+				Vector2f::new(320.0, win_sz.y as f32 - 130.0), Vector2f::new(300.0, 120.0),
+				Color::cyan());
+			window.set_view(&view);
+		}
+
+		if let Some(px) = cursor {
+			let at = window.map_pixel_to_coords_current_view(&px);
+			let at = frame.unapply(vec2::new(at.x, at.y));
+			cursor_info.set_string(&format!("({:.4e}, {:.4e})", at.x, at.y));
+			window.set_view(&hud);
+			window.draw(&cursor_info);
+			window.set_view(&view);
+		}
+
+		if show_ruler {
+			/* Aim for a bar about a fifth of the window wide */
+			let len = nice_length(view.size().x / 5.0);
+			let px = len / view.size().x * win_sz.x as f32;
+			let pos = Vector2f::new(win_sz.x as f32 - 20.0 - px, win_sz.y as f32 - 70.0);
+			let mut bar = RectangleShape::with_size(Vector2f::new(px, 3.0));
+			bar.set_position(pos);
+			bar.set_fill_color(&Color::white());
+			ruler_label.set_string(&format!("{:e}", len));
+			ruler_label.set_position((pos.x, pos.y - 20.0));
+			window.set_view(&hud);
+			window.draw(&bar);
+			window.draw(&ruler_label);
+			window.set_view(&view);
+		}
+
+		if let Some((from, to)) = drag {
+			/* An arrow along the new body's velocity */
+			let d = to - from;
+			let head = |angle: f32| {
+				let (sin, cos) = angle.sin_cos();
+				to - vec2::new(d.x * cos - d.y * sin, d.x * sin + d.y * cos) * 0.2
+			};
+			let mut arrow = VertexArray::new(PrimitiveType::Lines, 0);
+			for &(a, b) in &[(from, to), (to, head(0.5)), (to, head(-0.5))] {
+				arrow.append(&Vertex::with_pos_color(Vector2f::new(a.x, a.y), Color::cyan()));
+				arrow.append(&Vertex::with_pos_color(Vector2f::new(b.x, b.y), Color::cyan()));
+			}
+			window.draw(&arrow);
+		}
+
+		if measuring && ends.len() == 2 {
+			let mut line = VertexArray::new(PrimitiveType::Lines, 0);
+			for e in &ends {
+				line.append(&Vertex::with_pos_color(Vector2f::new(e.x, e.y), Color::yellow()));
+			}
+			window.draw(&line);
+
+			measure_info.set_string(&format!("distance: {:.4e}", ends[0].distance::<f32>(ends[1])));
+			window.set_view(&hud);
+			window.draw(&measure_info);
+			window.set_view(&view);
+		}
+
+		if let Some(i) = selected {
+			let o = &state[i];
+
+			let rad = 12.0 * view.size().x / win_sz.x as f32;
+			let mut ring = CircleShape::new(rad, 32);
+			let at = frame.apply(o.s);
+			ring.set_origin((rad, rad));
+			ring.set_position((at.x, at.y));
+			ring.set_fill_color(&Color::transparent());
+			ring.set_outline_color(&Color::yellow());
+			ring.set_outline_thickness(1.5 * view.size().x / win_sz.x as f32);
+			window.draw(&ring);
+
+			let mut lines = vec![
+				if names[i].is_empty() {
+					format!("body {}", i)
+				} else {
+					format!("body {} ({})", i, names[i])
+				},
+				format!("m: {:.4e}", o.m),
+				format!("r: {:.4e}", o.r),
+				format!("s: ({:.4e}, {:.4e})", o.s.x, o.s.y),
+				format!("v: ({:.4e}, {:.4e})", o.v.x, o.v.y),
+				format!("|v|: {:.4e}", o.v.norm::<f32>()),
+				format!("fixed: {}", o.fixed),
+				format!("v_esc: {:.4e}", escape_velocity(o.s, state.as_slice(), &cfg)),
+			];
+			if let Some(p) = primary_of(i, state.as_slice()) {
+				let el = orbital_elements(o, &state[p], cfg.g);
+				lines.push(format!("primary: {}", p));
+				lines.push(format!("a: {:.4e}", el.a));
+				lines.push(format!("e: {:.4}", el.e));
+				lines.push(format!("T: {:.4e}", el.period));
+
+				/* Start tracking afresh whenever the selection or its primary changes */
+				match apsides {
+					Some(ref mut aps) if aps.body == i && aps.primary == p =>
+						aps.update(state.as_slice()),
+					_ => apsides = Some(Apsides::new(i, p, state.as_slice())),
+				}
+				let aps = apsides.as_ref().unwrap();
+				lines.push(format!("r: {:.4e}", aps.current));
+				lines.push(format!("r_peri: {:.4e}", aps.peri.0));
+				lines.push(format!("r_apo: {:.4e}", aps.apo.0));
+
+				match closure {
+					Some(ref mut c) if c.body == i && c.primary == p => c.update(t, state.as_slice()),
+					_ => closure = Some(Closure::new(i, p, t, state.as_slice())),
+				}
+				if let Some(period) = closure.as_ref().unwrap().period {
+					lines.push(format!("period: {:.4e}", period));
+				}
+
+				let rad = 4.0 * view.size().x / win_sz.x as f32;
+				let mut marker = CircleShape::new(rad, 16);
+				marker.set_origin((rad, rad));
+				for &(off, color) in &[(aps.peri.1, Color::green()), (aps.apo.1, Color::red())] {
+					let at = frame.apply(state[p].s + off);
+					marker.set_position((at.x, at.y));
+					marker.set_fill_color(&color);
+					window.draw(&marker);
+				}
+			} else {
+				apsides = None;
+				closure = None;
+			}
+			sel_info.set_string(&lines.join("\n"));
+			window.set_view(&hud);
+			window.draw(&sel_info);
+			window.set_view(&view);
+		}
+
+		if show_stats {
+			let mut lines = vec![
+				format!("sigma_v: {:.4e}", velocity_dispersion(state.as_slice())),
+				format!("2KE/|PE|: {:.3}", virial_ratio(ke, pe)),
+			];
+
+			let primaries = (0..state.len())
+				.map(|i| primary_of(i, state.as_slice()))
+				.collect::<Vec<_>>();
+			let elements = primaries.iter()
+				.enumerate()
+				.map(|(i, p)| match *p {
+					Some(p) => orbital_elements(&state[i], &state[p], cfg.g),
+					None => OrbitalElements {
+						a: 0.0,
+						e: 0.0,
+						period: std::f32::INFINITY,
+					},
+				})
+				.collect::<Vec<_>>();
+			for (i, j, p, q) in find_resonances(elements.as_slice(), 0.01) {
+				/* A body and its own primary trivially share a period */
+				if primaries[i] == Some(j) || primaries[j] == Some(i) {
+					continue;
+				}
+				lines.push(format!("{}:{} resonance: {} & {}", p, q, i, j));
+			}
+			stats.set_string(&lines.join("\n"));
+			window.set_view(&hud);
+			window.draw(&stats);
+			window.set_view(&view);
+		}
+
+		recorder.frame(&window, snapshot).expect("cannot save frame");
+		snapshot = false;
 		window.display();
+
+		if let Some(ref mut p) = profile {
+			p.frame(integrate_time, phase.elapsed_time().as_seconds());
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	extern crate test;
+
+	use self::test::Bencher;
+	use super::*;
+
+	use std::cell::Cell;
+
+	use testing::{cluster, max_error};
+
+	/* A light body at perihelion of an e ~ 0.44 orbit about a heavy one, with G = 1 */
+	fn eccentric() -> (Vec<Object>, SimConfig) {
+		let body = |x: f32, vy: f32, m: f32| Object {
+			s: vec2::new(x, 0.0),
+			v: vec2::new(0.0, vy),
+			m,
+			r: 0.0,
+			fixed: false,
+		};
+		let cfg = SimConfig {
+			g: 1.0,
+			..SimConfig::default()
+		};
+		(vec![body(0.0, 0.0, 1.0), body(1.0, 1.2, 1.0e-3)], cfg)
+	}
+
+	fn energy(state: &[Object], cfg: &SimConfig) -> f32 {
+		kinetic_energy(state) + potential_energy(state, cfg)
+	}
+
+	/* The worst relative energy error over steps ticks of h */
+	fn energy_drift(integrator: Integrator, steps: usize, h: f32) -> f32 {
+		let (mut state, cfg) = eccentric();
+		let cfg = SimConfig { integrator, ..cfg };
+		let e0 = energy(state.as_slice(), &cfg);
+		let (mut t, mut step_dt, mut lead) = (0.0, h, None);
+		let mut worst = 0.0f32;
+		for _ in 0..steps {
+			tick(&mut state, &mut t, h, &mut step_dt, &mut lead, &cfg);
+			let now = synced(state.as_slice(), t, lead, &cfg);
+			worst = worst.max(((energy(now.as_slice(), &cfg) - e0) / e0).abs());
+		}
+		worst
+	}
+
+	#[test]
+	fn leapfrog_conserves_energy_on_eccentric_orbit() {
+		/* About ten orbits */
+		assert!(energy_drift(Integrator::Leapfrog, 30000, 0.005) < 1.0e-3);
+	}
+
+	#[test]
+	fn integrators_conserve_energy() {
+		for &integrator in [Integrator::Rk4, Integrator::Verlet, Integrator::Yoshida,
+			Integrator::Dopri].iter() {
+			let drift = energy_drift(integrator, 10000, 0.005);
+			assert!(drift < 1.0e-3, "{:?} drifted by {}", integrator, drift);
+		}
+	}
+
+	/* Run forward, turn every velocity around, and run back to where we started */
+	#[test]
+	fn leapfrog_is_reversible() {
+		const STEPS: usize = 2000;
+		let (init, cfg) = eccentric();
+		let cfg = SimConfig { integrator: Integrator::Leapfrog, ..cfg };
+		let run = |state: &[Object]| {
+			let mut state = state.to_vec();
+			let (mut t, mut step_dt, mut lead) = (0.0, 0.005, None);
+			for _ in 0..STEPS {
+				tick(&mut state, &mut t, 0.005, &mut step_dt, &mut lead, &cfg);
+			}
+			synced(state.as_slice(), t, lead, &cfg)
+		};
+		let turn = |state: &[Object]| state.iter()
+			.map(|o| Object { v: -o.v, ..*o })
+			.collect::<Vec<_>>();
+
+		let there = run(init.as_slice());
+		assert!(there[1].s.distance::<f32>(init[1].s) > 1.0);
+		let back = turn(run(turn(there.as_slice()).as_slice()).as_slice());
+		for (a, b) in init.iter().zip(back.iter()) {
+			assert!(a.s.distance::<f32>(b.s) < 1.0e-3, "{:?} != {:?}", a.s, b.s);
+			assert!(a.v.distance::<f32>(b.v) < 1.0e-3, "{:?} != {:?}", a.v, b.v);
+		}
+	}
+
+	#[test]
+	fn adaptive_steps_shrink_near_perihelion() {
+		let (mut state, cfg) = eccentric();
+		let cfg = SimConfig { tol: Some(1.0e-4), ..cfg };
+		let (mut t, mut step_dt, mut lead) = (0.0, 1.0, None);
+		/* Where the light body was after each tick, over about one orbit */
+		let mut seen = Vec::new();
+		for _ in 0..15 {
+			tick(&mut state, &mut t, 1.0, &mut step_dt, &mut lead, &cfg);
+			seen.push(state.clone());
+		}
+		assert!(lead.is_none());
+		assert!((t - 15.0).abs() < 1.0e-3, "t = {}", t);
+
+		let distance = |s: &Vec<Object>| s[1].s.distance::<f32>(s[0].s);
+		let by_distance = |a: &&Vec<Object>, b: &&Vec<Object>| distance(*a)
+			.partial_cmp(&distance(*b))
+			.unwrap();
+		/* The first step taken from each, starting from the same guess */
+		let step = |s: &Vec<Object>| {
+			integrate_adaptive(s.as_slice(), 0.0, &mut 1.0, 1.0, 1.0e-4, &cfg).1
+		};
+		let near = step(seen.iter().min_by(&by_distance).unwrap());
+		let far = step(seen.iter().max_by(&by_distance).unwrap());
+		assert!(far > 1.5 * near, "{} near vs {} far", near, far);
+	}
+
+	#[test]
+	fn headless_binary_keeps_its_distance() {
+		let cfg = SimConfig {
+			g: 1.0,
+			..SimConfig::default()
+		};
+		let (init, period) = circular_binary(1.0, 2.0, 1.0);
+		let h = 0.5 * period / 500.0;
+		let end = run_headless(init.clone(), MassSchedules::default(), 500, h, &cfg, &mut None,
+			&mut None);
+		assert!((end[0].s.distance::<f32>(end[1].s) - 2.0).abs() < 1.0e-4);
+		/* Half way around */
+		assert!(end[0].s.distance::<f32>(init[1].s) < 1.0e-3);
+	}
+
+	#[test]
+	fn check_passes() {
+		for &integrator in [Integrator::Rk4, Integrator::Verlet, Integrator::Leapfrog,
+			Integrator::Yoshida].iter() {
+			let cfg = SimConfig { integrator, ..SimConfig::default() };
+			assert!(check(&cfg), "{:?}", integrator);
+			/* In units where G is 1 */
+			assert!(check(&SimConfig { g: 1.0, ..cfg }), "{:?}", integrator);
+		}
+	}
+
+	#[test]
+	fn headless_logs_every_step() {
+		let temp = |name: &str| env::temp_dir().join(name).to_str().unwrap().to_string();
+		let energy = temp("nbody-test-headless-energy.csv");
+		let trajectory = temp("nbody-test-headless-trajectory.csv");
+		let (init, period) = circular_binary(1.0, 1.0, 1.0);
+		let cfg = SimConfig {
+			g: 1.0,
+			integrator: Integrator::Leapfrog,
+			..SimConfig::default()
+		};
+		let end = run_headless(init, MassSchedules::default(), 25, period / 100.0, &cfg,
+			&mut Some(EnergyLog::create(&energy).unwrap()),
+			&mut Some(TrajectoryLog::create(&trajectory).unwrap()));
+
+		let read = |path: &str| {
+			let csv = ::std::fs::read_to_string(path).unwrap();
+			::std::fs::remove_file(path).unwrap();
+			csv.lines().map(String::from).collect::<Vec<_>>()
+		};
+		let energy = read(&energy);
+		let trajectory = read(&trajectory);
+		assert_eq!(energy.len(), 1 + 25);
+		assert_eq!(trajectory.len(), 1 + 25 * 2);
+
+		/* The last rows are of the (synchronized) end state */
+		let last = trajectory[trajectory.len() - 1].split(',')
+			.map(|f| f.parse::<f32>().unwrap())
+			.collect::<Vec<_>>();
+		assert_eq!(last[1], 1.0);
+		assert_eq!(&last[2..], &[end[1].s.x, end[1].s.y, end[1].v.x, end[1].v.y]);
+	}
+
+	/* Every body pulled by every other, one at a time */
+	fn one_by_one(state: &[Object], cfg: &SimConfig) -> Vec<vec2<f32>> {
+		state.iter()
+			.enumerate()
+			.map(|(i, a)| state.iter()
+				.enumerate()
+				.filter(|&(j, _)| j != i)
+				.fold(vec2::ZERO, |acc, (_, b)| acc + grav(a, b, cfg)))
+			.collect()
+	}
+
+	#[test]
+	fn summations_agree() {
+		let mut state = cluster(200);
+		/* With some tracers mixed in */
+		for o in state.iter_mut().step_by(7) {
+			o.m = 0.0;
+		}
+		for &period in [None, Some(2.0e11)].iter() {
+			let cfg = SimConfig { period, ..SimConfig::default() };
+			let exact = one_by_one(state.as_slice(), &cfg);
+			for acc in [accelerations_serial(state.as_slice(), &cfg),
+				accelerations(state.as_slice(), &cfg)].iter() {
+				let err = max_error(acc.as_slice(), exact.as_slice());
+				assert!(err < 1.0e-4, "error {}", err);
+			}
+		}
+	}
+
+	#[test]
+	fn summation_choice() {
+		let cfg = SimConfig::default();
+		assert_eq!(cfg.summation(8), Summation::Serial);
+		assert_eq!(cfg.summation(100), Summation::Parallel);
+		assert_eq!(cfg.summation(5000), Summation::BarnesHut);
+		assert_eq!(SimConfig { simd: true, ..cfg }.summation(5000), Summation::Simd);
+		assert_eq!(SimConfig { force_bh: Some(true), ..cfg }.summation(8), Summation::BarnesHut);
+		assert_eq!(SimConfig { force_bh: Some(false), ..cfg }.summation(5000), Summation::Parallel);
+		assert_eq!(SimConfig { gravity: false, ..cfg }.summation(100), Summation::Off);
+	}
+
+	#[test]
+	fn accelerations_are_cached() {
+		let state = cluster(10);
+		let cfg = SimConfig::default();
+		let calls = Cell::new(0);
+		let count = || {
+			calls.set(calls.get() + 1);
+			accelerations_serial(state.as_slice(), &cfg)
+		};
+
+		let first = cached_accelerations(state.as_slice(), &cfg, &count);
+		assert_eq!(calls.get(), 1);
+		/* Velocities don't matter */
+		let mut moving = state.clone();
+		moving[3].v = moving[3].v * 2.0f32;
+		let again = cached_accelerations(moving.as_slice(), &cfg, &count);
+		assert_eq!(calls.get(), 1);
+		assert_eq!(max_error(again.as_slice(), first.as_slice()), 0.0);
+
+		/* But positions, masses, and settings do */
+		moving[3].s.x += 1.0e9;
+		cached_accelerations(moving.as_slice(), &cfg, &count);
+		moving[3].m *= 2.0;
+		cached_accelerations(moving.as_slice(), &cfg, &count);
+		cached_accelerations(moving.as_slice(), &SimConfig { eps: 1.0, ..cfg }, &count);
+		assert_eq!(calls.get(), 4);
+
+		/* A Verlet step's first stage is where the last one left off */
+		let verlet = SimConfig { integrator: Integrator::Verlet, ..cfg };
+		let once = integrate_verlet(state.as_slice(), 0.0, 1.0e5, &verlet);
+		let before = MISSES.with(|n| n.get());
+		integrate_verlet(once.as_slice(), 1.0e5, 1.0e5, &verlet);
+		assert_eq!(MISSES.with(|n| n.get()) - before, 1);
+	}
+
+	#[bench]
+	fn serial_8(b: &mut Bencher) {
+		let state = cluster(8);
+		let cfg = SimConfig::default();
+		b.iter(|| accelerations_serial(state.as_slice(), &cfg));
+	}
+
+	#[bench]
+	fn parallel_8(b: &mut Bencher) {
+		let state = cluster(8);
+		let cfg = SimConfig::default();
+		b.iter(|| accelerations(state.as_slice(), &cfg));
+	}
+
+	/* Pulling straight out of the bodies, as before they were split into arrays; both serially */
+	#[bench]
+	fn objects_5k(b: &mut Bencher) {
+		let state = cluster(5000);
+		let cfg = SimConfig::default();
+		b.iter(|| one_by_one(state.as_slice(), &cfg));
+	}
+
+	#[bench]
+	fn arrays_5k(b: &mut Bencher) {
+		let state = cluster(5000);
+		let cfg = SimConfig::default();
+		b.iter(|| accelerations_serial(state.as_slice(), &cfg));
+	}
+
+	#[test]
+	fn periodic_wrap() {
+		let w = wrap(vec2::new(0.6, 0.0), 1.0);
+		assert!((w.x + 0.4).abs() < 1.0e-6 && w.y == 0.0);
+		let w = wrap(vec2::new(-1.7, 2.2), 1.0);
+		assert!((w.x - 0.3).abs() < 1.0e-6 && (w.y - 0.2).abs() < 1.0e-6);
+		let d = min_image(vec2::new(0.9, -0.2), Some(1.0));
+		assert!((d.x + 0.1).abs() < 1.0e-6 && d.y == -0.2);
+		assert_eq!(min_image(vec2::new(0.9, 0.0), None).x, 0.9);
+
+		/* A lone body going out one side comes back in the other, just as fast */
+		let mut state = vec![Object {
+			s: vec2::new(0.45, 0.0),
+			v: vec2::new(1.0, 0.5),
+			m: 1.0,
+			r: 0.0,
+			fixed: false,
+		}];
+		let cfg = SimConfig { period: Some(1.0), ..SimConfig::default() };
+		let (mut t, mut step_dt, mut lead) = (0.0, 0.1, None);
+		tick(&mut state, &mut t, 0.1, &mut step_dt, &mut lead, &cfg);
+		assert!((state[0].s.x + 0.45).abs() < 1.0e-5 && (state[0].s.y - 0.05).abs() < 1.0e-5);
+		assert_eq!((state[0].v.x, state[0].v.y), (1.0, 0.5));
+	}
+
+	#[test]
+	fn tracers_leave_massive_bodies_alone() {
+		let cfg = SimConfig {
+			g: 1.0,
+			..SimConfig::default()
+		};
+		let (binary, period) = circular_binary(1.0, 1.0, 1.0);
+		let mut traced = binary.clone();
+		/* On roughly circular orbits well outside the binary */
+		for k in 1..4 {
+			let r = 2.0 * k as f32;
+			traced.push(Object {
+				s: vec2::new(0.0, r),
+				v: vec2::new(-(2.0 / r).sqrt(), 0.0),
+				m: 0.0,
+				..binary[0]
+			});
+		}
+		let run = |state: Vec<Object>| run_headless(state, MassSchedules::default(), 300,
+			period / 100.0, &cfg, &mut None, &mut None);
+
+		let (plain, traced) = (run(binary), run(traced));
+		for (a, b) in plain.iter().zip(traced.iter()) {
+			assert_eq!((a.s.x, a.s.y, a.v.x, a.v.y), (b.s.x, b.s.y, b.v.x, b.v.y));
+		}
+		/* While the tracers themselves do get pulled around */
+		assert!(traced[2..].iter().all(|o| o.v.y != 0.0));
+	}
+
+	/* Two bodies falling straight at each other pass through rather than flying apart */
+	#[test]
+	fn softening_caps_head_on_speed() {
+		const EPS: f32 = 0.1;
+		let cfg = SimConfig {
+			g: 1.0,
+			eps: EPS,
+			..SimConfig::default()
+		};
+		let body = |x: f32| Object {
+			s: vec2::new(x, 0.0),
+			v: vec2::new(0.0, 0.0),
+			m: 1.0,
+			r: 0.0,
+			fixed: false,
+		};
+		let mut state = vec![body(-1.0), body(1.0)];
+		/* Where all of the softened potential energy has gone into motion */
+		let most = (1.0 / EPS - 1.0 / (4.0 + EPS * EPS).sqrt()).sqrt();
+
+		let (mut t, mut step_dt, mut lead) = (0.0, 1.0e-3, None);
+		let mut fastest = 0.0f32;
+		for _ in 0..3000 {
+			tick(&mut state, &mut t, 1.0e-3, &mut step_dt, &mut lead, &cfg);
+			fastest = fastest.max(state[0].v.norm());
+		}
+		assert!(fastest.is_finite() && fastest < 1.01 * most, "{} vs {}", fastest, most);
+		assert!(fastest > 0.9 * most, "{} vs {}", fastest, most);
+		/* And they went through each other */
+		assert!(state[0].s.x > 0.0, "{:?}", state[0].s);
+	}
+
+	#[test]
+	fn substeps_are_capped() {
+		let (steps, left) = advance(0.35, 0.1, 10);
+		assert_eq!(steps, 3);
+		assert!((left - 0.05).abs() < 1.0e-6);
+		/* Too far behind to catch up, so the rest is dropped */
+		assert_eq!(advance(5.0, 0.1, 10), (10, 0.0));
+		assert_eq!(advance(0.0, 0.1, 10), (0, 0.0));
+	}
+
+	#[test]
+	fn spawned_velocity() {
+		let o = spawn(vec2::new(1.0, 1.0), vec2::new(3.0, 5.0), 7.0, 2.0);
+		assert_eq!((o.s.x, o.s.y, o.v.x, o.v.y, o.m), (1.0, 1.0, 1.0, 2.0, 7.0));
+		assert!(!o.fixed);
+	}
+
+	#[test]
+	fn sim_time_units() {
+		assert_eq!(format_sim_time(0.0), "0.0 s");
+		assert_eq!(format_sim_time(42.0), "42.0 s");
+		assert_eq!(format_sim_time(59.99), "1.0 min");
+		assert_eq!(format_sim_time(90.0), "1.5 min");
+		assert_eq!(format_sim_time(-7200.0), "-2.0 h");
+		assert_eq!(format_sim_time(3.0 * 86400.0), "3.0 d");
+		assert_eq!(format_sim_time(2000.0 * 365.25 * 86400.0), "2000.0 yr");
+	}
+
+	#[test]
+	fn speed_colors() {
+		let rgb = |c: Color| (c.r, c.g, c.b);
+		assert_eq!(rgb(speed_color(0.0, 10.0)), (0, 0, 255));
+		assert_eq!(rgb(speed_color(10.0, 10.0)), (255, 0, 0));
+		assert_eq!(rgb(speed_color(20.0, 10.0)), (255, 0, 0));
+		assert_eq!(rgb(speed_color(5.0, 0.0)), (0, 0, 255));
+	}
+
+	#[test]
+	fn disc_sprite() {
+		let img = disc(8, &Color::red());
+		assert_eq!((img.size().x, img.size().y), (16, 16));
+		assert_eq!(img.pixel_at(0, 0).a, 0);
+		let center = img.pixel_at(8, 8);
+		assert_eq!((center.r, center.g, center.b, center.a), (255, 0, 0, 255));
+		assert_eq!(img.pixel_at(15, 8).a, 255);
+	}
+
+	#[test]
+	fn sprite_scales() {
+		/* A 2 m body drawn with a 100 pixel texture */
+		assert_eq!(sprite_scale(1.0, 100, 50, 0.0), 0.02);
+		/* Point masses are still drawn floor across */
+		assert_eq!(sprite_scale(0.0, 10, 10, 5.0), 0.5);
+		assert_eq!(sprite_scale(1.0, 0, 0, 0.0), 2.0);
+	}
+
+	#[test]
+	fn remapping_removed_bodies() {
+		let removed = [1, 4];
+		let now = (0..6).map(|i| remap(i, &removed)).collect::<Vec<_>>();
+		assert_eq!(now, vec![Some(0), None, Some(1), Some(2), None, Some(3)]);
+		assert_eq!(remap(3, &[]), Some(3));
+
+		/* Drag keeps following its center, or stops when the center goes */
+		let mut cfg = SimConfig {
+			drag: Some("1 0 3 10".parse().unwrap()),
+			..SimConfig::default()
+		};
+		cfg.remove(&removed);
+		assert_eq!(cfg.drag.and_then(|d| d.center), Some(2));
+		cfg.remove(&[2]);
+		assert!(cfg.drag.is_none());
 	}
 }
@@ -0,0 +1,269 @@
+use std::f32::consts::PI;
+
+use math::vec2;
+use Object;
+
+/*
+ * Two bodies of mass m a distance d apart, circling their barycenter (at the origin), along with
+ * the period of their orbit
+ */
+pub fn circular_binary(m: f32, d: f32, g: f32) -> (Vec<Object>, f32) {
+	let v = (g * m / (2.0 * d)).sqrt();
+	let body = |side: f32| Object {
+		s: vec2::new(side * 0.5 * d, 0.0),
+		v: vec2::new(0.0, side * v),
+		m,
+		r: 0.0,
+		fixed: false,
+	};
+	(vec![body(1.0), body(-1.0)], PI * d / v)
+}
+
+/* Classical elements of the two-body orbit of one body about another */
+#[derive(Clone, Copy, Debug)]
+pub struct OrbitalElements {
+	/* Semi-major axis; negative for unbound (hyperbolic) orbits */
+	pub a: f32,
+	/* Eccentricity */
+	pub e: f32,
+	/* Period; infinite for unbound orbits */
+	pub period: f32,
+}
+
+pub fn orbital_elements(body: &Object, primary: &Object, g: f32) -> OrbitalElements {
+	let r = body.s - primary.s;
+	let v = body.v - primary.v;
+	let mu = g * (body.m + primary.m);
+
+	/* Specific orbital energy and angular momentum */
+	let eps = 0.5 * v.normsq() - mu / r.norm::<f32>();
+	let h = r.x * v.y - r.y * v.x;
+
+	let a = -mu / (2.0 * eps);
+	let e = (1.0 + 2.0 * eps * h * h / (mu * mu)).max(0.0).sqrt();
+	let period = if a > 0.0 {
+		2.0 * PI * (a * a * a / mu).sqrt()
+	} else {
+		::std::f32::INFINITY
+	};
+
+	OrbitalElements { a, e, period }
+}
+
+/* The body which exerts the strongest pull on state[i] */
+pub fn primary_of(i: usize, state: &[Object]) -> Option<usize> {
+	let a = &state[i];
+	state.iter()
+		.enumerate()
+		.filter(|&(j, b)| j != i && b.m != 0.0)
+		.map(|(j, b)| (j, b.m / (b.s - a.s).normsq()))
+		.max_by(|&(_, x), &(_, y)| x.partial_cmp(&y).unwrap())
+		.map(|(j, _)| j)
+}
+
+/* Tracks the closest and farthest approach of a body to its primary */
+pub struct Apsides {
+	pub body: usize,
+	pub primary: usize,
+	pub current: f32,
+	/* Distance and offset from the primary at periapsis and apoapsis */
+	pub peri: (f32, vec2<f32>),
+	pub apo: (f32, vec2<f32>),
+}
+
+impl Apsides {
+	pub fn new(body: usize, primary: usize, state: &[Object]) -> Apsides {
+		let r = state[body].s - state[primary].s;
+		let d = r.norm();
+		Apsides {
+			body: body,
+			primary: primary,
+			current: d,
+			peri: (d, r),
+			apo: (d, r),
+		}
+	}
+
+	pub fn update(&mut self, state: &[Object]) {
+		let r = state[self.body].s - state[self.primary].s;
+		let d = r.norm();
+		self.current = d;
+		if d < self.peri.0 {
+			self.peri = (d, r);
+		}
+		if d > self.apo.0 {
+			self.apo = (d, r);
+		}
+	}
+}
+
+/*
+ * Watches for a body to return to where it started (relative to its primary) with about the same
+ * velocity, which only happens for orbits which neither precess nor are chaotic
+ */
+pub struct Closure {
+	pub body: usize,
+	pub primary: usize,
+	t0: f32,
+	s0: vec2<f32>,
+	v0: vec2<f32>,
+	/* Whether we have gotten far enough away to start looking for a return */
+	left: bool,
+	last: (f32, f32),
+	/* Time taken by the most recent closed loop */
+	pub period: Option<f32>,
+}
+
+impl Closure {
+	/* Tolerances, relative to the starting distance and speed */
+	const NEAR: f32 = 0.01;
+	const FAR: f32 = 0.1;
+	const VTOL: f32 = 0.05;
+
+	pub fn new(body: usize, primary: usize, t: f32, state: &[Object]) -> Closure {
+		let s0 = state[body].s - state[primary].s;
+		Closure {
+			body: body,
+			primary: primary,
+			t0: t,
+			s0: s0,
+			v0: state[body].v - state[primary].v,
+			left: false,
+			last: (t, 0.0),
+			period: None,
+		}
+	}
+
+	pub fn update(&mut self, t: f32, state: &[Object]) {
+		let s = state[self.body].s - state[self.primary].s;
+		let v = state[self.body].v - state[self.primary].v;
+		let scale = self.s0.norm::<f32>();
+		let d = (s - self.s0).norm::<f32>();
+
+		if !self.left {
+			self.left = d > Closure::FAR * scale;
+		} else if d > self.last.1 && self.last.1 < Closure::NEAR * scale
+			&& (v - self.v0).norm::<f32>() < Closure::VTOL * self.v0.norm::<f32>() {
+			/* We just passed our closest approach to the start */
+			self.period = Some(self.last.0 - self.t0);
+			self.t0 = self.last.0;
+			self.left = false;
+		}
+		self.last = (t, d);
+	}
+}
+
+/*
+ * Find pairs of orbits whose periods are close to a ratio of small integers. Each resonance is
+ * reported as (i, j, p, q) where T_j / T_i is within a relative tol of p / q, with p >= q.
+ */
+pub fn find_resonances(elements: &[OrbitalElements], tol: f32) -> Vec<(usize, usize, u32, u32)> {
+	const MAX_ORDER: u32 = 5;
+	let mut found = Vec::new();
+
+	for (i, a) in elements.iter().enumerate() {
+		for (j, b) in elements.iter().enumerate().skip(i + 1) {
+			if !a.period.is_finite() || !b.period.is_finite() {
+				continue;
+			}
+
+			/* Put the shorter period first */
+			let (i, j, ratio) = if a.period <= b.period {
+				(i, j, b.period / a.period)
+			} else {
+				(j, i, a.period / b.period)
+			};
+
+			/* Try the lowest-order ratios first */
+			'search: for q in 1..MAX_ORDER + 1 {
+				for p in q..MAX_ORDER + 1 {
+					let target = p as f32 / q as f32;
+					if gcd(p, q) == 1 && (ratio - target).abs() <= tol * target {
+						found.push((i, j, p, q));
+						break 'search;
+					}
+				}
+			}
+		}
+	}
+	found
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+	if b == 0 { a } else { gcd(b, a % b) }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn with_period(period: f32) -> OrbitalElements {
+		OrbitalElements { a: 1.0, e: 0.0, period }
+	}
+
+	#[test]
+	fn circular_elements() {
+		let (state, period) = circular_binary(1.0, 1.0, 1.0);
+		let el = orbital_elements(&state[0], &state[1], 1.0);
+		assert!((el.a - 1.0).abs() < 1.0e-5, "a = {}", el.a);
+		assert!(el.e < 1.0e-3, "e = {}", el.e);
+		assert!((el.period - period).abs() < 1.0e-4 * period, "{} != {}", el.period, period);
+		assert_eq!(primary_of(0, state.as_slice()), Some(1));
+	}
+
+	#[test]
+	fn unbound_elements() {
+		let (mut state, _) = circular_binary(1.0, 1.0, 1.0);
+		state[0].v = state[0].v * 3.0f32;
+		let el = orbital_elements(&state[0], &state[1], 1.0);
+		assert!(el.a < 0.0);
+		assert!(el.e > 1.0);
+		assert!(el.period.is_infinite());
+	}
+
+	/* Trace out an ellipse by hand; Closure only cares that it comes back around */
+	#[test]
+	fn closure_of_ellipse() {
+		let period = 3.0f32;
+		let w = 2.0 * PI / period;
+		let dt = period / 1000.0;
+		let primary = Object {
+			s: vec2::new(0.0, 0.0),
+			v: vec2::new(0.0, 0.0),
+			m: 1.0,
+			r: 0.0,
+			fixed: false,
+		};
+		let at = |t: f32| {
+			let (sin, cos) = (w * t).sin_cos();
+			vec![Object {
+				s: vec2::new(2.0 * cos, sin),
+				v: vec2::new(-2.0 * w * sin, w * cos),
+				m: 0.0,
+				..primary
+			}, primary]
+		};
+
+		let mut closure = Closure::new(0, 1, 0.0, at(0.0).as_slice());
+		let mut apsides = Apsides::new(0, 1, at(0.0).as_slice());
+		for k in 1..2500 {
+			let t = k as f32 * dt;
+			closure.update(t, at(t).as_slice());
+			apsides.update(at(t).as_slice());
+		}
+		let found = closure.period.expect("never closed");
+		assert!((found - period).abs() < 0.01 * period, "{} != {}", found, period);
+		assert!((apsides.peri.0 - 1.0).abs() < 1.0e-3);
+		assert!((apsides.apo.0 - 2.0).abs() < 1.0e-3);
+	}
+
+	#[test]
+	fn resonances() {
+		let elements = [with_period(1.0), with_period(2.01), with_period(7.3)];
+		assert_eq!(find_resonances(&elements, 0.01), vec![(0, 1, 2, 1)]);
+
+		/* Order doesn't matter, and unbound orbits never resonate */
+		let elements = [with_period(4.5), with_period(3.0), with_period(::std::f32::INFINITY)];
+		assert_eq!(find_resonances(&elements, 0.01), vec![(1, 0, 3, 2)]);
+	}
+}
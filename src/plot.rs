@@ -0,0 +1,126 @@
+use std::collections::VecDeque;
+
+use sfml::system::Vector2f;
+use sfml::graphics::{Color, PrimitiveType, RectangleShape, RenderTarget, Shape, Transformable,
+	Vertex, VertexArray};
+
+/* Draw the translucent background of a HUD panel */
+pub fn panel<T: RenderTarget>(target: &mut T, pos: Vector2f, size: Vector2f) {
+	let mut bg = RectangleShape::with_size(size);
+	bg.set_position(pos);
+	bg.set_fill_color(&Color::rgba(0, 0, 0, 192));
+	bg.set_outline_color(&Color::rgba(255, 255, 255, 64));
+	bg.set_outline_thickness(1.0);
+	target.draw(&bg);
+}
+
+/* A scrolling line plot of the last few samples of some quantity */
+pub struct Plot {
+	samples: VecDeque<f32>,
+	cap: usize,
+}
+
+impl Plot {
+	pub fn new(cap: usize) -> Plot {
+		Plot {
+			samples: VecDeque::with_capacity(cap),
+			cap: cap,
+		}
+	}
+
+	pub fn push(&mut self, v: f32) {
+		if self.samples.len() == self.cap {
+			self.samples.pop_front();
+		}
+		self.samples.push_back(v);
+	}
+
+	pub fn clear(&mut self) {
+		self.samples.clear();
+	}
+
+	/* Draw the plot into the (screen-space) rectangle at pos with the given size */
+	pub fn draw<T: RenderTarget>(&self, target: &mut T, pos: Vector2f, size: Vector2f, color: Color) {
+		panel(target, pos, size);
+
+		if self.samples.is_empty() {
+			return;
+		}
+
+		/* Scale vertically to fit whatever range we have seen */
+		let lo = self.samples.iter().cloned().fold(::std::f32::INFINITY, f32::min);
+		let hi = self.samples.iter().cloned().fold(::std::f32::NEG_INFINITY, f32::max);
+		let span = if hi > lo { hi - lo } else { 1.0 };
+		let dx = size.x / (self.cap.max(2) - 1) as f32;
+
+		let mut line = VertexArray::new(PrimitiveType::LineStrip, 0);
+		for (i, v) in self.samples.iter().enumerate() {
+			let x = pos.x + i as f32 * dx;
+			let y = pos.y + size.y * (1.0 - (v - lo) / span);
+			line.append(&Vertex::with_pos_color(Vector2f::new(x, y), color));
+		}
+		target.draw(&line);
+	}
+}
+
+/* Draw a bar chart of counts into the (screen-space) rectangle at pos with the given size */
+pub fn draw_bars<T: RenderTarget>(target: &mut T, counts: &[u32], pos: Vector2f, size: Vector2f,
+	color: Color) {
+	panel(target, pos, size);
+
+	let max = counts.iter().cloned().max().unwrap_or(0);
+	if max == 0 {
+		return;
+	}
+
+	let w = size.x / counts.len() as f32;
+	for (i, &c) in counts.iter().enumerate() {
+		let h = size.y * c as f32 / max as f32;
+		let mut bar = RectangleShape::with_size(Vector2f::new(w - 1.0, h));
+		bar.set_position((pos.x + i as f32 * w, pos.y + size.y - h));
+		bar.set_fill_color(&color);
+		target.draw(&bar);
+	}
+}
+
+/* Draw lines every step (in world units) across the part of the world the view covers */
+pub fn draw_grid<T: RenderTarget>(target: &mut T, center: Vector2f, size: Vector2f, step: f32,
+	color: Color) {
+	if !(step > 0.0) {
+		return;
+	}
+
+	let (lo, hi) = (center - size / 2.0, center + size / 2.0);
+	let mut lines = VertexArray::new(PrimitiveType::Lines, 0);
+	let mut x = (lo.x / step).floor() * step;
+	while x <= hi.x {
+		lines.append(&Vertex::with_pos_color(Vector2f::new(x, lo.y), color));
+		lines.append(&Vertex::with_pos_color(Vector2f::new(x, hi.y), color));
+		x += step;
+	}
+	let mut y = (lo.y / step).floor() * step;
+	while y <= hi.y {
+		lines.append(&Vertex::with_pos_color(Vector2f::new(lo.x, y), color));
+		lines.append(&Vertex::with_pos_color(Vector2f::new(hi.x, y), color));
+		y += step;
+	}
+	target.draw(&lines);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn keeps_the_last_samples() {
+		let mut plot = Plot::new(3);
+		for v in 0..5 {
+			plot.push(v as f32);
+		}
+		assert_eq!(plot.samples.iter().cloned().collect::<Vec<_>>(), vec![2.0, 3.0, 4.0]);
+		plot.clear();
+		assert!(plot.samples.is_empty());
+		plot.push(7.0);
+		assert_eq!(plot.samples.len(), 1);
+	}
+}
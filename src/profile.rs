@@ -0,0 +1,52 @@
+use std::cell::Cell;
+
+use sfml::system::Clock;
+
+thread_local!(static FORCES: Cell<f32> = Cell::new(0.0));
+
+/* Run f, charging the time it takes to the force calculation */
+pub fn time_forces<T, F: FnOnce() -> T>(f: F) -> T {
+	let clk = Clock::start();
+	let ret = f();
+	let elapsed = clk.elapsed_time().as_seconds();
+	FORCES.with(|forces| forces.set(forces.get() + elapsed));
+	ret
+}
+
+/* Averages where each frame's time goes, and prints it every so often */
+pub struct Profile {
+	frames: u32,
+	integrate: f32,
+	render: f32,
+}
+
+impl Profile {
+	const REPORT: u32 = 120;
+
+	pub fn new() -> Profile {
+		FORCES.with(|forces| forces.set(0.0));
+		Profile {
+			frames: 0,
+			integrate: 0.0,
+			render: 0.0,
+		}
+	}
+
+	/* Record one frame; integrate includes any time spent on forces */
+	pub fn frame(&mut self, integrate: f32, render: f32) {
+		self.integrate += integrate;
+		self.render += render;
+		self.frames += 1;
+		if self.frames < Profile::REPORT {
+			return;
+		}
+
+		let forces = FORCES.with(|forces| forces.replace(0.0));
+		let ms = 1000.0 / self.frames as f32;
+		println!("forces: {:.2}ms, integrate: {:.2}ms, render: {:.2}ms", forces * ms,
+			(self.integrate - forces) * ms, self.render * ms);
+		self.frames = 0;
+		self.integrate = 0.0;
+		self.render = 0.0;
+	}
+}
@@ -0,0 +1,277 @@
+use rayon::prelude::*;
+
+use diag::bounding_box;
+use math::{vec2, Additive};
+use {grav, min_image, Object, SimConfig};
+
+/* Deep enough for any sane scene; coincident bodies just share a leaf past this */
+const MAX_DEPTH: u32 = 32;
+
+struct Node {
+	center: vec2<f32>,
+	half: f32,
+	m: f32,
+	/* Center of mass of everything in this node */
+	com: vec2<f32>,
+	children: Vec<usize>,
+	/* Only leaves hold bodies directly */
+	bodies: Vec<usize>,
+}
+
+impl Node {
+	fn contains(&self, p: vec2<f32>) -> bool {
+		(p.x - self.center.x).abs() <= self.half && (p.y - self.center.y).abs() <= self.half
+	}
+}
+
+/* A quadtree over the bodies, for approximating far-away clusters by their center of mass */
+pub struct BarnesHut<'a> {
+	state: &'a [Object],
+	nodes: Vec<Node>,
+}
+
+impl<'a> BarnesHut<'a> {
+	pub fn new(state: &'a [Object]) -> BarnesHut<'a> {
+		let mut tree = BarnesHut {
+			state,
+			nodes: Vec::new(),
+		};
+		if !state.is_empty() {
+			let (lo, hi) = bounding_box(state);
+			let ext = hi - lo;
+			tree.build((0..state.len()).collect(), (lo + hi) * 0.5, 0.5 * ext.x.max(ext.y), 0);
+		}
+		tree
+	}
+
+	fn build(&mut self, bodies: Vec<usize>, center: vec2<f32>, half: f32, depth: u32) -> usize {
+		let m = bodies.iter().map(|&i| self.state[i].m).sum::<f32>();
+		let com = if m > 0.0 {
+			bodies.iter()
+				.map(|&i| self.state[i].s * self.state[i].m)
+				.fold(vec2::ZERO, |a, v| a + v) * (1.0 / m)
+		} else {
+			center
+		};
+
+		let node = self.nodes.len();
+		self.nodes.push(Node {
+			center,
+			half,
+			m,
+			com,
+			children: Vec::new(),
+			bodies: Vec::new(),
+		});
+		if bodies.len() <= 1 || depth == MAX_DEPTH {
+			self.nodes[node].bodies = bodies;
+			return node;
+		}
+
+		let mut quads = vec![Vec::new(); 4];
+		for i in bodies {
+			let s = self.state[i].s;
+			quads[(s.x >= center.x) as usize + 2 * (s.y >= center.y) as usize].push(i);
+		}
+		let half = 0.5 * half;
+		for (q, bodies) in quads.into_iter().enumerate() {
+			if bodies.is_empty() {
+				continue;
+			}
+			let off = vec2::new(if q & 1 == 0 { -half } else { half },
+				if q & 2 == 0 { -half } else { half });
+			let child = self.build(bodies, center + off, half, depth + 1);
+			self.nodes[node].children.push(child);
+		}
+		node
+	}
+
+	/*
+	 * The acceleration of body i, treating any node which is smaller than theta times its
+	 * distance away as a single point mass
+	 */
+	pub fn acceleration(&self, i: usize, cfg: &SimConfig) -> vec2<f32> {
+		let a = &self.state[i];
+		let mut acc = vec2::ZERO;
+		let mut stack = if self.nodes.is_empty() { vec![] } else { vec![0] };
+		while let Some(n) = stack.pop() {
+			let node = &self.nodes[n];
+			/* Nothing but tracers in here */
+			if node.m == 0.0 {
+				continue;
+			}
+			if node.children.is_empty() {
+				for &j in node.bodies.iter().filter(|&&j| j != i) {
+					acc = acc + grav(a, &self.state[j], cfg);
+				}
+				continue;
+			}
+
+			let size = 2.0 * node.half;
+			let dsq = min_image(node.com - a.s, cfg.period).normsq();
+			if !node.contains(a.s) && size * size < cfg.theta * cfg.theta * dsq {
+				acc = acc + grav(a, &Object {
+					s: node.com,
+					v: vec2::ZERO,
+					m: node.m,
+					r: 0.0,
+					fixed: false,
+				}, cfg);
+			} else {
+				stack.extend(node.children.iter());
+			}
+		}
+		acc
+	}
+
+	pub fn accelerations(&self, cfg: &SimConfig) -> Vec<vec2<f32>> {
+		(0..self.state.len())
+			.into_par_iter()
+			.map(|i| self.acceleration(i, cfg))
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	extern crate test;
+
+	use self::test::Bencher;
+	use super::*;
+	use accelerations as direct;
+	use std::time::Instant;
+	use testing::{cluster, max_error};
+	use {accelerations_serial, Summation};
+
+	#[test]
+	fn close_to_direct() {
+		let state = cluster(2000);
+		let cfg = SimConfig::default();
+		let err = max_error(BarnesHut::new(state.as_slice()).accelerations(&cfg).as_slice(),
+			direct(state.as_slice(), &cfg).as_slice());
+		assert!(err < 0.01, "error {}", err);
+	}
+
+	#[test]
+	fn exact_when_always_opened() {
+		let state = cluster(300);
+		let cfg = SimConfig {
+			theta: 0.0,
+			..SimConfig::default()
+		};
+		let err = max_error(BarnesHut::new(state.as_slice()).accelerations(&cfg).as_slice(),
+			direct(state.as_slice(), &cfg).as_slice());
+		assert!(err < 1.0e-4, "error {}", err);
+	}
+
+	#[test]
+	fn tracers_pull_on_nothing() {
+		let mut state = cluster(200);
+		let cfg = SimConfig::default();
+		let before = BarnesHut::new(state.as_slice()).accelerations(&cfg);
+		let mut tracer = state[0];
+		tracer.m = 0.0;
+		for k in 0..4 {
+			tracer.s = state[k].s * 0.5;
+			state.push(tracer);
+		}
+		let after = BarnesHut::new(state.as_slice()).accelerations(&cfg);
+		assert!(max_error(&after[..200], before.as_slice()) < 0.01);
+		assert!(after[200..].iter().all(|a| a.norm::<f32>() > 0.0));
+	}
+
+	#[test]
+	fn degenerate() {
+		let cfg = SimConfig::default();
+		assert!(BarnesHut::new(&[]).accelerations(&cfg).is_empty());
+
+		/* Everything in one spot bottoms out rather than recursing forever */
+		let state = vec![cluster(1)[0]; 10];
+		let acc = BarnesHut::new(state.as_slice()).accelerations(&cfg);
+		assert!(acc.iter().all(|a| a.x == 0.0 && a.y == 0.0));
+	}
+
+	/*
+	 * Time Barnes-Hut against summing every pair (serially or not, as the simulation would) over
+	 * a range of scene sizes, to see where bh_above should be. Run it with
+	 * cargo test --release crossover -- --ignored --nocapture
+	 */
+	#[test]
+	#[ignore]
+	fn crossover() {
+		const RUNS: u32 = 5;
+		let time = |f: &Fn()| {
+			let start = Instant::now();
+			for _ in 0..RUNS {
+				f();
+			}
+			let elapsed = start.elapsed();
+			(elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1.0e-9) / RUNS as f64
+		};
+
+		let cfg = SimConfig { force_bh: Some(false), ..SimConfig::default() };
+		let mut crossover = None;
+		println!("{:>6} {:>12} {:>12}", "n", "pairwise", "barnes-hut");
+		let mut n = 16;
+		while n <= 16384 {
+			let state = cluster(n);
+			let pairwise = if cfg.summation(n) == Summation::Serial {
+				time(&|| { accelerations_serial(state.as_slice(), &cfg); })
+			} else {
+				time(&|| { direct(state.as_slice(), &cfg); })
+			};
+			let bh = time(&|| { BarnesHut::new(state.as_slice()).accelerations(&cfg); });
+			println!("{:>6} {:>12.3e} {:>12.3e}", n, pairwise, bh);
+			if crossover.is_none() && bh < pairwise {
+				crossover = Some(n);
+			}
+			n *= 2;
+		}
+		match crossover {
+			Some(n) => println!("Barnes-Hut is faster from about {} bodies", n),
+			None => println!("Barnes-Hut never caught up"),
+		}
+	}
+
+	#[bench]
+	fn barnes_hut_1k(b: &mut Bencher) {
+		let state = cluster(1024);
+		let cfg = SimConfig::default();
+		b.iter(|| BarnesHut::new(state.as_slice()).accelerations(&cfg));
+	}
+
+	#[bench]
+	fn direct_1k(b: &mut Bencher) {
+		let state = cluster(1024);
+		let cfg = SimConfig::default();
+		b.iter(|| direct(state.as_slice(), &cfg));
+	}
+
+	#[bench]
+	fn barnes_hut_4k(b: &mut Bencher) {
+		let state = cluster(4096);
+		let cfg = SimConfig::default();
+		b.iter(|| BarnesHut::new(state.as_slice()).accelerations(&cfg));
+	}
+
+	#[bench]
+	fn direct_4k(b: &mut Bencher) {
+		let state = cluster(4096);
+		let cfg = SimConfig::default();
+		b.iter(|| direct(state.as_slice(), &cfg));
+	}
+
+	#[bench]
+	fn barnes_hut_16k(b: &mut Bencher) {
+		let state = cluster(16384);
+		let cfg = SimConfig::default();
+		b.iter(|| BarnesHut::new(state.as_slice()).accelerations(&cfg));
+	}
+
+	#[bench]
+	fn direct_16k(b: &mut Bencher) {
+		let state = cluster(16384);
+		let cfg = SimConfig::default();
+		b.iter(|| direct(state.as_slice(), &cfg));
+	}
+}
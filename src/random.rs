@@ -0,0 +1,125 @@
+use std::f32::consts::PI;
+
+use diag::{kinetic_energy, potential_energy};
+use math::vec2;
+use {Object, SimConfig};
+
+/* A small xorshift generator, so scenes come out the same everywhere for a given seed */
+pub struct Rng(u64);
+
+impl Rng {
+	pub fn new(seed: u64) -> Rng {
+		const MIX: u64 = 0x9e37_79b9_7f4a_7c15;
+		/* Zero is a fixed point of xorshift */
+		Rng(if seed == MIX { MIX } else { seed ^ MIX })
+	}
+
+	pub fn next_u64(&mut self) -> u64 {
+		self.0 ^= self.0 << 13;
+		self.0 ^= self.0 >> 7;
+		self.0 ^= self.0 << 17;
+		self.0
+	}
+
+	/* Uniform in [0, 1) */
+	pub fn next_f32(&mut self) -> f32 {
+		(self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+	}
+
+	/* A random direction in the plane */
+	fn direction(&mut self) -> vec2<f32> {
+		let (sin, cos) = (2.0 * PI * self.next_f32()).sin_cos();
+		vec2::new(cos, sin)
+	}
+}
+
+/*
+ * n bodies of total mass m drawn from a Plummer sphere of scale radius a, flattened into the plane.
+ * Speeds follow the Plummer distribution function, and are then scaled so the system starts out
+ * in virial equilibrium.
+ */
+pub fn plummer(n: usize, m: f32, a: f32, g: f32, seed: u64) -> Vec<Object> {
+	let mut rng = Rng::new(seed);
+	let mut state = (0..n)
+		.map(|_| {
+			/* Invert the cumulative mass, skipping the sparse outer tail */
+			let x = 0.999 * rng.next_f32() + 1.0e-6;
+			let r = a / (x.powf(-2.0 / 3.0) - 1.0).sqrt();
+
+			/* Rejection sample q = v / v_esc from q^2 (1 - q^2)^(7/2) */
+			let q = loop {
+				let (x, y) = (rng.next_f32(), 0.1 * rng.next_f32());
+				if y < x * x * (1.0 - x * x).powf(3.5) {
+					break x;
+				}
+			};
+			let v_esc = (2.0 * g * m / a).sqrt() * (1.0 + r * r / (a * a)).powf(-0.25);
+
+			Object {
+				s: rng.direction() * r,
+				v: rng.direction() * (q * v_esc),
+				m: m / n as f32,
+				r: 0.0,
+				fixed: false,
+			}
+		})
+		.collect::<Vec<Object>>();
+
+	/* Point masses, as the distribution function assumes */
+	let cfg = SimConfig {
+		g,
+		eps: 0.0,
+		..SimConfig::default()
+	};
+	let (ke, pe) = (kinetic_energy(state.as_slice()), potential_energy(state.as_slice(), &cfg));
+	if ke > 0.0 && pe < 0.0 {
+		let k = (-pe / (2.0 * ke)).sqrt();
+		for o in state.iter_mut() {
+			o.v = o.v * k;
+		}
+	}
+	state
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use diag::virial_ratio;
+
+	#[test]
+	fn same_seed_same_scene() {
+		let (a, b) = (plummer(100, 1.0, 1.0, 1.0, 42), plummer(100, 1.0, 1.0, 1.0, 42));
+		for (a, b) in a.iter().zip(b.iter()) {
+			assert_eq!((a.s.x, a.s.y, a.v.x, a.v.y, a.m), (b.s.x, b.s.y, b.v.x, b.v.y, b.m));
+		}
+
+		let c = plummer(100, 1.0, 1.0, 1.0, 43);
+		assert!(a.iter().zip(c.iter()).any(|(a, c)| a.s.x != c.s.x));
+	}
+
+	#[test]
+	fn uniform() {
+		/* Including the seed xorshift can't get out of */
+		for seed in 0..4 {
+			let mut rng = Rng::new(seed);
+			let xs = (0..1000).map(|_| rng.next_f32()).collect::<Vec<_>>();
+			assert!(xs.iter().all(|&x| x >= 0.0 && x < 1.0));
+			let mean = xs.iter().sum::<f32>() / xs.len() as f32;
+			assert!((mean - 0.5).abs() < 0.05, "seed {} mean {}", seed, mean);
+		}
+	}
+
+	#[test]
+	fn plummer_starts_in_equilibrium() {
+		let state = plummer(500, 2.0, 3.0, 0.5, 1);
+		let cfg = SimConfig {
+			g: 0.5,
+			..SimConfig::default()
+		};
+		assert_eq!(state.len(), 500);
+		assert!((state.iter().map(|o| o.m).sum::<f32>() - 2.0).abs() < 1.0e-4);
+		let pe = potential_energy(state.as_slice(), &cfg);
+		assert!((virial_ratio(kinetic_energy(state.as_slice()), pe) - 1.0).abs() < 1.0e-3);
+	}
+}
@@ -0,0 +1,69 @@
+use std::fs;
+use std::io;
+
+use sfml::graphics::{RenderWindow, Texture};
+
+/* Zero-padded so that tools globbing for frames see them in order */
+pub fn frame_path(n: u32) -> String {
+	format!("frames/frame_{:06}.png", n)
+}
+
+/* Saves frames to frames/, either on request or every so often */
+pub struct Recorder {
+	/* Save every this many frames; zero for only on request */
+	every: u32,
+	frame: u32,
+	saved: u32,
+}
+
+impl Recorder {
+	pub fn new(every: u32) -> Recorder {
+		Recorder {
+			every,
+			frame: 0,
+			saved: 0,
+		}
+	}
+
+	/* Call once per frame, after drawing but before displaying */
+	pub fn frame(&mut self, window: &RenderWindow, now: bool) -> io::Result<()> {
+		let due = self.every != 0 && self.frame % self.every == 0;
+		self.frame += 1;
+		if now || due {
+			self.save(window)
+		} else {
+			Ok(())
+		}
+	}
+
+	fn save(&mut self, window: &RenderWindow) -> io::Result<()> {
+		let fail = |what: &str| io::Error::new(io::ErrorKind::Other, format!("cannot {}", what));
+
+		fs::create_dir_all("frames")?;
+		let sz = window.size();
+		let mut tex = Texture::new(sz.x, sz.y).ok_or_else(|| fail("create capture texture"))?;
+		tex.update_from_render_window(window, 0, 0);
+		let img = tex.copy_to_image().ok_or_else(|| fail("copy capture texture"))?;
+		let path = frame_path(self.saved);
+		if !img.save_to_file(&path) {
+			return Err(fail(&format!("save {}", path)));
+		}
+		self.saved += 1;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn frame_paths() {
+		assert_eq!(frame_path(0), "frames/frame_000000.png");
+		assert_eq!(frame_path(42), "frames/frame_000042.png");
+		assert_eq!(frame_path(1234567), "frames/frame_1234567.png");
+		/* Sorting by name is sorting by frame */
+		assert!(frame_path(9) < frame_path(10));
+		assert!(frame_path(99999) < frame_path(100000));
+	}
+}
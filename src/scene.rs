@@ -0,0 +1,561 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::io::{BufRead, Read};
+use std::str::FromStr;
+
+use serde_json;
+
+use math::vec2;
+use force::{Background, Drag};
+use mass::MassSchedule;
+use {Integrator, Object};
+
+/* Each thing a scene file spells out */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+	Count,
+	Size,
+	G,
+	X,
+	Y,
+	Vx,
+	Vy,
+	Mass,
+	Texture,
+	Setting,
+	Integrator,
+	Tolerance,
+	Softening,
+	SpeedOfLight,
+	Drag,
+	Background,
+	Restitution,
+	MassSchedule,
+	Timestep,
+	TimeMult,
+}
+
+impl fmt::Display for Field {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match *self {
+			Field::Count => "number of objects",
+			Field::Size => "universe size",
+			Field::G => "gravitational constant",
+			Field::X => "x",
+			Field::Y => "y",
+			Field::Vx => "vx",
+			Field::Vy => "vy",
+			Field::Mass => "mass",
+			Field::Texture => "texture",
+			Field::Setting => "setting",
+			Field::Integrator => "integrator",
+			Field::Tolerance => "tolerance",
+			Field::Softening => "softening length",
+			Field::SpeedOfLight => "speed of light",
+			Field::Drag => "drag",
+			Field::Background => "background potential",
+			Field::Restitution => "coefficient of restitution",
+			Field::MassSchedule => "mass schedule",
+			Field::Timestep => "timestep",
+			Field::TimeMult => "time multiplier",
+		})
+	}
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+	Io(io::Error),
+	Json(serde_json::Error),
+	/* The (1-based) line ran out before this field */
+	Missing { line: usize, field: Field },
+	Invalid { line: usize, field: Field, value: String },
+	/* Like Invalid, but for the (0-based) body of a JSON scene */
+	InvalidBody { body: usize, field: Field, value: String },
+}
+
+impl fmt::Display for SceneError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			SceneError::Io(ref e) => write!(f, "{}", e),
+			SceneError::Json(ref e) => write!(f, "{}", e),
+			SceneError::Missing { line, field } => write!(f, "line {}: missing {}", line, field),
+			SceneError::Invalid { line, field, ref value } =>
+				write!(f, "line {}: invalid {}: `{}'", line, field, value),
+			SceneError::InvalidBody { body, field, ref value } =>
+				write!(f, "object {}: invalid {}: `{}'", body, field, value),
+		}
+	}
+}
+
+impl Error for SceneError {}
+
+impl From<io::Error> for SceneError {
+	fn from(e: io::Error) -> SceneError {
+		SceneError::Io(e)
+	}
+}
+
+impl From<serde_json::Error> for SceneError {
+	fn from(e: serde_json::Error) -> SceneError {
+		SceneError::Json(e)
+	}
+}
+
+/* How a scene would like to be simulated; anything missing is left up to the command line */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Settings {
+	pub g: Option<f32>,
+	pub integrator: Option<Integrator>,
+	pub tol: Option<f32>,
+	pub eps: Option<f32>,
+	/* Turns on post-Newtonian corrections */
+	pub c: Option<f32>,
+	pub drag: Option<Drag>,
+	pub background: Option<Background>,
+	pub restitution: Option<f32>,
+	/* Simulated time per step */
+	pub timestep: Option<f32>,
+	/* Simulated time per second of real time */
+	pub time_mult: Option<f32>,
+}
+
+/* The radius, settings, bodies, texture paths, mass schedules, and names (or "") of a scene */
+pub type Parsed =
+	(f32, Settings, Vec<Object>, Vec<String>, Vec<Option<MassSchedule>>, Vec<String>);
+
+fn field<'a, T, I>(iter: &mut I, line: usize, field: Field) -> Result<T, SceneError> where
+T: FromStr,
+I: Iterator<Item = &'a str> {
+	let tok = iter.next().ok_or(SceneError::Missing { line, field })?;
+	tok.parse().map_err(|_| SceneError::Invalid { line, field, value: tok.into() })
+}
+
+fn optional_field<'a, T, I>(iter: &mut I, line: usize, field: Field) -> Result<Option<T>, SceneError> where
+T: FromStr,
+I: Iterator<Item = &'a str> {
+	match iter.next() {
+		Some(tok) => tok.parse()
+			.map(Some)
+			.map_err(|_| SceneError::Invalid { line, field, value: tok.into() }),
+		None => Ok(None),
+	}
+}
+
+/* Where a body's texture lives; no texture at all is an empty path */
+fn texture_path(name: Option<String>) -> String {
+	match name {
+		Some(ref name) if !name.is_empty() => format!("img/{}", name),
+		_ => String::new(),
+	}
+}
+
+/* A table of (t, m) samples, which only makes sense in strictly increasing order of t */
+fn mass_table(samples: Vec<(f32, f32)>) -> Option<MassSchedule> {
+	if samples.windows(2).all(|w| w[0].0 < w[1].0) {
+		Some(MassSchedule::Table(samples))
+	} else {
+		None
+	}
+}
+
+/* "dm=RATE" or "m=T:M,T:M,..." */
+fn mass_schedule(tok: &str) -> Option<MassSchedule> {
+	if tok.starts_with("dm=") {
+		tok[3..].parse().ok().map(MassSchedule::Rate)
+	} else if tok.starts_with("m=") {
+		let samples = tok[2..].split(',')
+			.map(|sample| {
+				let mut tm = sample.splitn(2, ':');
+				match (tm.next().map(str::parse), tm.next().map(str::parse)) {
+					(Some(Ok(t)), Some(Ok(m))) => Some((t, m)),
+					_ => None,
+				}
+			})
+			.collect::<Option<Vec<(f32, f32)>>>()?;
+		mass_table(samples)
+	} else {
+		None
+	}
+}
+
+/* Apply one "key = value" line of a scene's header */
+fn setting(settings: &mut Settings, line: &str, l: usize) -> Result<(), SceneError> {
+	let mut kv = line.splitn(2, '=');
+	let key = kv.next().unwrap_or("").trim();
+	let value = kv.next().unwrap_or("").trim();
+	let invalid = |field| SceneError::Invalid { line: l, field, value: value.into() };
+	match key {
+		"integrator" => settings.integrator = Some(value.parse()
+			.map_err(|_| invalid(Field::Integrator))?),
+		"tolerance" => settings.tol = Some(value.parse().map_err(|_| invalid(Field::Tolerance))?),
+		"softening" => settings.eps = Some(value.parse().map_err(|_| invalid(Field::Softening))?),
+		"drag" => settings.drag = Some(value.parse().map_err(|_| invalid(Field::Drag))?),
+		"background" => settings.background = Some(value.parse()
+			.map_err(|_| invalid(Field::Background))?),
+		"restitution" => settings.restitution = Some(value.parse()
+			.map_err(|_| invalid(Field::Restitution))?),
+		"c" => settings.c = Some(value.parse().map_err(|_| invalid(Field::SpeedOfLight))?),
+		"timestep" => settings.timestep = Some(value.parse()
+			.map_err(|_| invalid(Field::Timestep))?),
+		"time-mult" => settings.time_mult = Some(value.parse()
+			.map_err(|_| invalid(Field::TimeMult))?),
+		_ => return Err(SceneError::Invalid { line: l, field: Field::Setting, value: key.into() }),
+	}
+	Ok(())
+}
+
+/*
+ * Read a scene: an optional header of "key = value" lines (integrator, tolerance, softening, c,
+ * drag, background, restitution, timestep, and time-mult), the number of bodies, the radius of the
+ * universe (optionally followed by G), and then one body per line as
+ * "x y vx vy m [r] [texture] [dm=RATE | m=T:M,...] [name=NAME] [fixed]".
+ */
+pub fn parse_scene<R: BufRead>(reader: R) -> Result<Parsed, SceneError> {
+	let mut lines = reader.lines();
+	let mut l = 0;
+	/* Running out of lines is just an empty one, which then lacks whatever comes first */
+	let mut next = || -> io::Result<(usize, String)> {
+		l += 1;
+		lines.next().unwrap_or_else(|| Ok(String::new())).map(|line| (l, line))
+	};
+
+	let mut settings = Settings::default();
+	let (mut l, mut line) = next()?;
+	while line.contains('=') {
+		setting(&mut settings, line.as_str(), l)?;
+		let (nl, nline) = next()?;
+		l = nl;
+		line = nline;
+	}
+
+	let n: usize = field(&mut line.split_whitespace(), l, Field::Count)?;
+	let (l, line) = next()?;
+	let mut iter = line.split_whitespace();
+	let r: f32 = field(&mut iter, l, Field::Size)?;
+	settings.g = optional_field(&mut iter, l, Field::G)?;
+
+	let mut state = Vec::new();
+	let mut paths = Vec::new();
+	let mut schedules = Vec::new();
+	let mut names = Vec::new();
+	for _ in 0..n {
+		let (l, line) = next()?;
+		let mut iter = line.split_whitespace().peekable();
+		let s = vec2::new(field(&mut iter, l, Field::X)?, field(&mut iter, l, Field::Y)?);
+		let v = vec2::new(field(&mut iter, l, Field::Vx)?, field(&mut iter, l, Field::Vy)?);
+		let m = field(&mut iter, l, Field::Mass)?;
+		/* Older scenes go straight from the mass to the texture; those bodies are points */
+		let r = iter.peek().and_then(|tok| tok.parse::<f32>().ok());
+		if r.is_some() {
+			iter.next();
+		}
+		state.push(Object {
+			s,
+			v,
+			m,
+			r: r.unwrap_or(0.0),
+			fixed: false,
+		});
+
+		let mut texture = None;
+		let mut schedule = None;
+		let mut name = String::new();
+		for tok in iter {
+			if tok == "fixed" {
+				state.last_mut().unwrap().fixed = true;
+			} else if tok.starts_with("name=") {
+				name = tok[5..].to_string();
+			} else if tok.contains('=') {
+				schedule = Some(mass_schedule(tok).ok_or(SceneError::Invalid {
+					line: l,
+					field: Field::MassSchedule,
+					value: tok.into(),
+				})?);
+			} else if texture.is_none() {
+				texture = Some(tok.to_string());
+			} else {
+				return Err(SceneError::Invalid { line: l, field: Field::Texture, value: tok.into() });
+			}
+		}
+		paths.push(texture_path(texture));
+		schedules.push(schedule);
+		names.push(name);
+	}
+	Ok((r, settings, state, paths, schedules, names))
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SceneObject {
+	#[serde(default)]
+	pub name: Option<String>,
+	pub position: vec2<f32>,
+	pub velocity: vec2<f32>,
+	pub mass: f32,
+	/* Relative to img/, as in the plain format */
+	#[serde(default)]
+	pub texture: Option<String>,
+	#[serde(default)]
+	pub radius: f32,
+	/* Mass gained per second */
+	#[serde(default)]
+	pub mass_rate: Option<f32>,
+	/* (t, m) samples, in order of t; wins over mass_rate */
+	#[serde(default)]
+	pub mass_table: Option<Vec<(f32, f32)>>,
+	/* Pinned in place, but still pulling on everything else */
+	#[serde(default)]
+	pub fixed: bool,
+}
+
+/* The JSON flavor of a scene */
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Scene {
+	pub radius: f32,
+	#[serde(default)]
+	pub g: Option<f32>,
+	#[serde(default)]
+	pub integrator: Option<Integrator>,
+	#[serde(default)]
+	pub tolerance: Option<f32>,
+	#[serde(default)]
+	pub softening: Option<f32>,
+	#[serde(default)]
+	pub c: Option<f32>,
+	#[serde(default)]
+	pub drag: Option<Drag>,
+	#[serde(default)]
+	pub background: Option<Background>,
+	#[serde(default)]
+	pub restitution: Option<f32>,
+	#[serde(default)]
+	pub timestep: Option<f32>,
+	#[serde(default)]
+	pub time_mult: Option<f32>,
+	pub objects: Vec<SceneObject>,
+}
+
+/* Like parse_scene, but for a JSON Scene */
+pub fn parse_scene_json<R: Read>(reader: R) -> Result<Parsed, SceneError> {
+	let scene: Scene = serde_json::from_reader(reader)?;
+	let state = scene.objects.iter()
+		.map(|o| Object {
+			s: o.position,
+			v: o.velocity,
+			m: o.mass,
+			r: o.radius,
+			fixed: o.fixed,
+		})
+		.collect();
+	let paths = scene.objects.iter()
+		.map(|o| texture_path(o.texture.clone()))
+		.collect();
+	let schedules = scene.objects.iter()
+		.enumerate()
+		.map(|(i, o)| match (&o.mass_table, o.mass_rate) {
+			(&Some(ref table), _) => mass_table(table.clone())
+				.map(Some)
+				.ok_or_else(|| SceneError::InvalidBody {
+					body: i,
+					field: Field::MassSchedule,
+					value: format!("{:?}", table),
+				}),
+			(&None, Some(rate)) => Ok(Some(MassSchedule::Rate(rate))),
+			(&None, None) => Ok(None),
+		})
+		.collect::<Result<Vec<_>, _>>()?;
+	let names = scene.objects.iter()
+		.map(|o| o.name.clone().unwrap_or_default())
+		.collect();
+	let settings = Settings {
+		g: scene.g,
+		integrator: scene.integrator,
+		tol: scene.tolerance,
+		eps: scene.softening,
+		c: scene.c,
+		drag: scene.drag,
+		background: scene.background,
+		restitution: scene.restitution,
+		timestep: scene.timestep,
+		time_mult: scene.time_mult,
+	};
+	Ok((scene.radius, settings, state, paths, schedules, names))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parse(text: &str) -> Parsed {
+		parse_scene(text.as_bytes()).unwrap()
+	}
+
+	fn error(text: &str) -> String {
+		match parse_scene(text.as_bytes()) {
+			Ok(parsed) => panic!("parsed {:?}", parsed),
+			Err(e) => e.to_string(),
+		}
+	}
+
+	#[test]
+	fn plain() {
+		let (r, settings, state, paths, schedules, names) = parse("integrator = verlet
+softening = 100
+3
+1e11 6.67e-11
+0 0 0 0 2e30 7e8 sun.png name=Sun fixed
+1.5e11 0 0 29780 6e24 6.4e6 dm=-1
+-2e11 0 0 -24000 6e23 0 m=0:6e23,10:3e23
+");
+		assert_eq!(r, 1.0e11);
+		assert_eq!(settings, Settings {
+			g: Some(6.67e-11),
+			integrator: Some(Integrator::Verlet),
+			eps: Some(100.0),
+			..Settings::default()
+		});
+		assert_eq!(state.len(), 3);
+		assert!(state[0].fixed && !state[1].fixed);
+		assert_eq!((state[1].s.x, state[1].v.y, state[1].m, state[1].r),
+			(1.5e11, 29780.0, 6.0e24, 6.4e6));
+		assert_eq!((state[2].s.x, state[2].v.y), (-2.0e11, -24000.0));
+		assert_eq!(paths, vec!["img/sun.png", "", ""]);
+		assert_eq!(schedules, vec![
+			None,
+			Some(MassSchedule::Rate(-1.0)),
+			Some(MassSchedule::Table(vec![(0.0, 6.0e23), (10.0, 3.0e23)])),
+		]);
+		assert_eq!(names, vec!["Sun", "", ""]);
+	}
+
+	#[test]
+	fn plain_without_g() {
+		let (r, settings, state, _, _, _) = parse("1\n5\n1 2 3 4 5 6\n");
+		assert_eq!((r, settings), (5.0, Settings::default()));
+		assert_eq!((state[0].s.y, state[0].v.x, state[0].r), (2.0, 3.0, 6.0));
+	}
+
+	#[test]
+	fn plain_without_radius() {
+		let (_, _, state, paths, _, names) =
+			parse("2\n10\n1 2 3 4 5 earth.png\n6 7 8 9 10 name=Moon\n");
+		assert_eq!((state[0].s.x, state[0].v.y, state[0].m, state[0].r), (1.0, 4.0, 5.0, 0.0));
+		assert_eq!((state[1].m, state[1].r), (10.0, 0.0));
+		assert_eq!(paths, vec!["img/earth.png", ""]);
+		assert_eq!(names, vec!["", "Moon"]);
+	}
+
+	#[test]
+	fn plain_errors() {
+		assert_eq!(error("1\n10\n0 0 1 2\n"), "line 3: missing mass");
+		assert_eq!(error("1\n10\n0 0 fast 2 1 1\n"), "line 3: invalid vx: `fast'");
+		assert_eq!(error("softening = 5\n1\n10\n0 0 0 0\n"), "line 4: missing mass");
+		/* Running out of bodies early */
+		assert_eq!(error("2\n10\n0 0 0 0 1 1\n"), "line 4: missing x");
+		assert_eq!(error(""), "line 1: missing number of objects");
+		assert_eq!(error("1\nbig\n"), "line 2: invalid universe size: `big'");
+		assert_eq!(error("1\n10\n0 0 0 0 1 1 a.png b.png\n"), "line 3: invalid texture: `b.png'");
+		assert_eq!(error("1\n10\n0 0 0 0 1 1 m=5:1,2:3\n"),
+			"line 3: invalid mass schedule: `m=5:1,2:3'");
+	}
+
+	#[test]
+	fn header_errors() {
+		assert_eq!(error("integrator = euler\n1\n10\n0 0 0 0 1 1\n"),
+			"line 1: invalid integrator: `euler'");
+		assert_eq!(error("integrator = rk4\ngravity = on\n"), "line 2: invalid setting: `gravity'");
+		for name in ["rk4", "verlet", "leapfrog", "dopri", "yoshida"].iter() {
+			let (_, settings, _, _, _, _) = parse(&format!("integrator = {}\n0\n10\n", name));
+			assert_eq!(settings.integrator, name.parse().ok());
+		}
+	}
+
+	#[test]
+	fn json_round_trip() {
+		let scene = Scene {
+			radius: 3.0e11,
+			g: Some(1.0),
+			integrator: Some(Integrator::Leapfrog),
+			tolerance: None,
+			softening: Some(10.0),
+			c: None,
+			drag: Some(Drag {
+				linear: 0.5,
+				quadratic: 0.0,
+				center: Some(0),
+				scale: Some(1.0e9),
+			}),
+			background: None,
+			restitution: Some(0.8),
+			timestep: Some(60.0),
+			time_mult: None,
+			objects: vec![SceneObject {
+				name: Some("Sun".into()),
+				position: vec2::new(1.0, -2.0),
+				velocity: vec2::new(0.0, 3.5),
+				mass: 2.0e30,
+				texture: Some("sun.png".into()),
+				radius: 7.0e8,
+				mass_rate: Some(-4.0e9),
+				mass_table: None,
+				fixed: true,
+			}, SceneObject {
+				name: None,
+				position: vec2::new(1.5e11, 0.0),
+				velocity: vec2::new(0.0, 29780.0),
+				mass: 6.0e24,
+				texture: None,
+				radius: 0.0,
+				mass_rate: Some(1.0),
+				mass_table: Some(vec![(0.0, 6.0e24), (1.0, 7.0e24)]),
+				fixed: false,
+			}],
+		};
+		let json = serde_json::to_string(&scene).unwrap();
+		let (r, settings, state, paths, schedules, names) =
+			parse_scene_json(json.as_bytes()).unwrap();
+
+		assert_eq!(r, scene.radius);
+		assert_eq!(settings, Settings {
+			g: scene.g,
+			integrator: scene.integrator,
+			tol: scene.tolerance,
+			eps: scene.softening,
+			c: scene.c,
+			drag: scene.drag,
+			background: scene.background,
+			restitution: scene.restitution,
+			timestep: scene.timestep,
+			time_mult: scene.time_mult,
+		});
+		for (o, so) in state.iter().zip(scene.objects.iter()) {
+			assert_eq!((o.s.x, o.s.y, o.v.x, o.v.y), (so.position.x, so.position.y, so.velocity.x,
+				so.velocity.y));
+			assert_eq!((o.m, o.r, o.fixed), (so.mass, so.radius, so.fixed));
+		}
+		assert_eq!(paths, vec!["img/sun.png", ""]);
+		/* Tables win over rates */
+		assert_eq!(schedules, vec![
+			Some(MassSchedule::Rate(-4.0e9)),
+			Some(MassSchedule::Table(vec![(0.0, 6.0e24), (1.0, 7.0e24)])),
+		]);
+		assert_eq!(names, vec!["Sun", ""]);
+	}
+
+	#[test]
+	fn json_errors() {
+		let bad = r#"{"radius": 10, "objects": [
+			{"position": [0, 0], "velocity": [0, 0], "mass": 1},
+			{"position": [1, 0], "velocity": [0, 1], "mass": 1, "mass_table": [[0, 1], [0, 2]]}
+		]}"#;
+		match parse_scene_json(bad.as_bytes()) {
+			Err(SceneError::InvalidBody { body: 1, field: Field::MassSchedule, .. }) => (),
+			other => panic!("{:?}", other),
+		}
+
+		match parse_scene_json(r#"{"radius": 10}"#.as_bytes()) {
+			Err(SceneError::Json(_)) => (),
+			other => panic!("{:?}", other),
+		}
+	}
+}
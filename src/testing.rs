@@ -0,0 +1,19 @@
+/* Fixtures shared by the tests of the different ways of summing forces */
+
+use math::vec2;
+use random::plummer;
+use {Object, G};
+
+/* A cluster of about a solar mass over about an AU, as --random makes */
+pub fn cluster(n: usize) -> Vec<Object> {
+	plummer(n, 2.0e30, 1.5e11, G, 1)
+}
+
+/* The furthest a is from b, relative to the largest acceleration in b */
+pub fn max_error(a: &[vec2<f32>], b: &[vec2<f32>]) -> f32 {
+	let scale = b.iter().map(|a| a.norm::<f32>()).fold(0.0, f32::max);
+	a.iter()
+		.zip(b.iter())
+		.map(|(a, b)| a.distance::<f32>(*b))
+		.fold(0.0, f32::max) / scale
+}
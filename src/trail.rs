@@ -0,0 +1,137 @@
+use std::collections::VecDeque;
+
+use sfml::system::Vector2f;
+use sfml::graphics::{Color, PrimitiveType, RenderTarget, Vertex, VertexArray};
+
+use frame::Frame;
+use math::vec2;
+use Object;
+
+/* The last few positions of every body, oldest first, as seen in the frame of the time */
+pub struct Trails {
+	paths: Vec<VecDeque<vec2<f32>>>,
+	len: usize,
+}
+
+impl Trails {
+	pub fn new(len: usize) -> Trails {
+		Trails {
+			paths: Vec::new(),
+			len: len,
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.len
+	}
+
+	pub fn set_len(&mut self, len: usize) {
+		self.len = len;
+		for p in self.paths.iter_mut() {
+			while p.len() > len {
+				p.pop_front();
+			}
+		}
+	}
+
+	/* Record where everything is now, as seen in frame; new bodies start with fresh trails */
+	pub fn push(&mut self, state: &[Object], frame: &Frame) {
+		self.paths.resize(state.len(), VecDeque::new());
+		if self.len == 0 {
+			return;
+		}
+		for (p, o) in self.paths.iter_mut().zip(state.iter()) {
+			if p.len() == self.len {
+				p.pop_front();
+			}
+			p.push_back(frame.apply(o.s));
+		}
+	}
+
+	/* Forget the trails of bodies which have been removed, given in ascending order */
+	pub fn remove(&mut self, removed: &[usize]) {
+		for &i in removed.iter().rev() {
+			if i < self.paths.len() {
+				self.paths.remove(i);
+			}
+		}
+	}
+
+	/*
+	 * Draw each trail, fading out towards its tail. The view turns the frames the trails were
+	 * recorded in to the one they're drawn in.
+	 */
+	pub fn draw<T: RenderTarget>(&self, target: &mut T, view: &Frame, color: Color) {
+		for p in &self.paths {
+			let mut line = VertexArray::new(PrimitiveType::LineStrip, 0);
+			for (i, &s) in p.iter().enumerate() {
+				let at = view.apply(s);
+				let alpha = (255 * (i + 1) / p.len()) as u8;
+				line.append(&Vertex::with_pos_color(Vector2f::new(at.x, at.y),
+					Color::rgba(color.r, color.g, color.b, alpha)));
+			}
+			target.draw(&line);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn at(x: f32) -> Object {
+		Object {
+			s: vec2::new(x, 0.0),
+			v: vec2::new(0.0, 0.0),
+			m: 1.0,
+			r: 0.0,
+			fixed: false,
+		}
+	}
+
+	fn xs(trails: &Trails, i: usize) -> Vec<f32> {
+		trails.paths[i].iter().map(|s| s.x).collect()
+	}
+
+	#[test]
+	fn keeps_the_newest() {
+		let mut trails = Trails::new(3);
+		for k in 0..5 {
+			trails.push(&[at(k as f32)], &Frame::inertial());
+		}
+		assert_eq!(xs(&trails, 0), vec![2.0, 3.0, 4.0]);
+
+		trails.set_len(2);
+		assert_eq!(xs(&trails, 0), vec![3.0, 4.0]);
+
+		/* New bodies start fresh, and removed ones take their trails with them */
+		trails.push(&[at(5.0), at(-1.0)], &Frame::inertial());
+		assert_eq!(xs(&trails, 0), vec![4.0, 5.0]);
+		assert_eq!(xs(&trails, 1), vec![-1.0]);
+		trails.remove(&[0]);
+		assert_eq!(trails.paths.len(), 1);
+		assert_eq!(xs(&trails, 0), vec![-1.0]);
+
+		trails.set_len(0);
+		trails.push(&[at(6.0)], &Frame::inertial());
+		assert!(trails.paths[0].is_empty());
+	}
+
+	#[test]
+	fn recorded_in_frame() {
+		let mut trails = Trails::new(4);
+		let frame = Frame {
+			origin: vec2::new(10.0, 0.0),
+			angle: 0.0,
+		};
+		/* Following a body, its trail stays put however far it goes */
+		for k in 0..3 {
+			let x = 10.0 * k as f32;
+			trails.push(&[at(x)], &Frame { origin: vec2::new(x, 0.0), ..frame });
+		}
+		assert_eq!(xs(&trails, 0), vec![0.0, 0.0, 0.0]);
+
+		trails.push(&[at(12.0)], &frame);
+		assert_eq!(xs(&trails, 0), vec![0.0, 0.0, 0.0, 2.0]);
+	}
+}